@@ -0,0 +1,248 @@
+// Brainf*ck to LC-3 compiler.
+//
+// Programs are compiled to a flat LC-3 object image that the `lc-3` VM can
+// load at its origin. The generated code uses the following conventions :
+//
+// R0 : I/O scratch register for TRAP OUT/GETC.
+// R1 : tape pointer, an absolute address into VM memory.
+// R2 : value of the current cell.
+// R3 : 0x00FF mask used to wrap cells to 8 bits.
+// R4 : scratch register holding far jump targets.
+//
+// The tape lives in VM memory starting at `TAPE_START` and relies on the VM
+// clearing memory before loading the image.
+
+// Origin of the generated image.
+pub const ORIGIN: u16 = 0x3000;
+// First cell of the tape.
+pub const TAPE_START: u16 = 0x4000;
+
+// Trap vectors used by the generated code.
+const TRAP_GETC: u16 = 0x20;
+const TRAP_OUT: u16 = 0x21;
+const TRAP_HALT: u16 = 0x25;
+
+// Registers used by the generated code.
+const R0: u16 = 0;
+const R1: u16 = 1;
+const R2: u16 = 2;
+const R3: u16 = 3;
+const R4: u16 = 4;
+
+// Branch condition bits.
+const BR_N: u16 = 0b100;
+const BR_Z: u16 = 0b010;
+const BR_P: u16 = 0b001;
+
+// ADD DR, SR1, #imm5
+fn add_imm(dr: u16, sr1: u16, imm5: i16) -> u16 {
+    assert!((-16..=15).contains(&imm5));
+    (0b0001 << 12) | (dr << 9) | (sr1 << 6) | (1 << 5) | (imm5 as u16 & 0x1f)
+}
+
+// AND DR, SR1, SR2
+fn and_reg(dr: u16, sr1: u16, sr2: u16) -> u16 {
+    (0b0101 << 12) | (dr << 9) | (sr1 << 6) | sr2
+}
+
+// BRnzp #offset9
+fn br(cond: u16, offset9: i16) -> u16 {
+    (cond << 9) | (offset9 as u16 & 0x1ff)
+}
+
+// LD DR, #offset9
+fn ld(dr: u16, offset9: i16) -> u16 {
+    (0b0010 << 12) | (dr << 9) | (offset9 as u16 & 0x1ff)
+}
+
+// LDR DR, BaseR, #offset6
+fn ldr(dr: u16, base: u16, offset6: i16) -> u16 {
+    (0b0110 << 12) | (dr << 9) | (base << 6) | (offset6 as u16 & 0x3f)
+}
+
+// STR SR, BaseR, #offset6
+fn str(sr: u16, base: u16, offset6: i16) -> u16 {
+    (0b0111 << 12) | (sr << 9) | (base << 6) | (offset6 as u16 & 0x3f)
+}
+
+// JMP BaseR
+fn jmp(base: u16) -> u16 {
+    (0b1100 << 12) | (base << 6)
+}
+
+// TRAP trapvect8
+fn trap(vector: u16) -> u16 {
+    (0b1111 << 12) | (vector & 0xff)
+}
+
+// Code buffer, addresses are computed relative to `ORIGIN`.
+struct Emitter {
+    code: Vec<u16>,
+}
+
+impl Emitter {
+    fn address(&self) -> u16 {
+        ORIGIN + self.code.len() as u16
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.code.push(word)
+    }
+
+    // Add a signed amount to a register, split into imm5 sized chunks.
+    fn add(&mut self, reg: u16, mut amount: i32) {
+        while amount != 0 {
+            let chunk = amount.clamp(-16, 15);
+            self.emit(add_imm(reg, reg, chunk as i16));
+            amount -= chunk;
+        }
+    }
+
+    // Jump to `target` when the current cell matches `cond`, the target is
+    // stored inline so jumps aren't limited by the 9-bit branch offset.
+    // Returns the index of the inline target so it can be patched later.
+    //
+    //     LDR R2, R1, #0
+    //     BR(!cond) #3
+    //     LD  R4, #1
+    //     JMP R4
+    //     .FILL target
+    fn jump_if(&mut self, cond: u16, target: u16) -> usize {
+        self.emit(ldr(R2, R1, 0));
+        self.emit(br(!cond & 0b111, 3));
+        self.emit(ld(R4, 1));
+        self.emit(jmp(R4));
+        self.emit(target);
+        self.code.len() - 1
+    }
+}
+
+// Compile a Brainf*ck program to an LC-3 image, the first word of the image
+// is its origin.
+pub fn compile(source: &str) -> Vec<u16> {
+    let code: Vec<_> = source.chars().filter(|c| "<>+-.,[]".contains(*c)).collect();
+    let mut emitter = Emitter { code: vec![] };
+    // Loads R1 and R3 from the constants that follow, then skips over them.
+    emitter.emit(ld(R1, 2));
+    emitter.emit(ld(R3, 2));
+    emitter.emit(br(BR_N | BR_Z | BR_P, 2));
+    emitter.emit(TAPE_START);
+    emitter.emit(0x00ff);
+
+    // Open loops as (index of the exit target, address of the loop body).
+    let mut loops: Vec<(usize, u16)> = vec![];
+    let mut pc = 0;
+    while pc < code.len() {
+        let inst = code[pc];
+        match inst {
+            '>' | '<' | '+' | '-' => {
+                // Fold runs of pointer moves and cell updates.
+                let (up, down) = if inst == '>' || inst == '<' {
+                    ('>', '<')
+                } else {
+                    ('+', '-')
+                };
+                let mut amount = 0i32;
+                while pc < code.len() && (code[pc] == up || code[pc] == down) {
+                    amount += if code[pc] == up { 1 } else { -1 };
+                    pc += 1;
+                }
+                if up == '>' {
+                    emitter.add(R1, amount);
+                } else {
+                    let amount = amount.rem_euclid(256);
+                    if amount != 0 {
+                        emitter.emit(ldr(R2, R1, 0));
+                        emitter.add(R2, if amount > 128 { amount - 256 } else { amount });
+                        emitter.emit(and_reg(R2, R2, R3));
+                        emitter.emit(str(R2, R1, 0));
+                    }
+                }
+                continue;
+            }
+            '.' => {
+                emitter.emit(ldr(R0, R1, 0));
+                emitter.emit(trap(TRAP_OUT));
+            }
+            ',' => {
+                emitter.emit(trap(TRAP_GETC));
+                emitter.emit(str(R0, R1, 0));
+            }
+            '[' => {
+                // The exit target is patched once the matching ']' is seen.
+                let exit = emitter.jump_if(BR_Z, 0);
+                loops.push((exit, emitter.address()));
+            }
+            ']' => {
+                let (exit, body) = loops
+                    .pop()
+                    .unwrap_or_else(|| panic!("Unmatched ']' at pc : {}", pc));
+                emitter.jump_if(BR_N | BR_P, body);
+                emitter.code[exit] = emitter.address();
+            }
+            _ => unreachable!(),
+        }
+        pc += 1;
+    }
+    if !loops.is_empty() {
+        panic!("Unmatched '[' in program");
+    }
+    emitter.emit(trap(TRAP_HALT));
+    if emitter.address() > TAPE_START {
+        panic!("Program doesn't fit below the tape at {:#06x}", TAPE_START);
+    }
+
+    let mut image = vec![ORIGIN];
+    image.extend(emitter.code);
+    image
+}
+
+// Serialize an image to the big-endian LC-3 object file format.
+pub fn to_bytes(image: &[u16]) -> Vec<u8> {
+    image.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lc_3::console::ScriptedConsole;
+    use lc_3::vm::{Register, VirtualMachine};
+
+    // Printed by the native HALT routine.
+    const HALT: &[u8] = b"\n----- Halting the processor ----- \n";
+
+    // Compile a program, run it on the LC-3 VM and return what it displayed.
+    fn run(source: &str, input: &[u8]) -> Vec<u8> {
+        let console = ScriptedConsole::new(input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        vm.load_image("program", &to_bytes(&compile(source)))
+            .expect("image fits in memory");
+        vm.registers[Register::Pc as usize] = ORIGIN;
+        vm.run();
+        console.output()
+    }
+
+    #[test]
+    fn hello_world() {
+        let output = run(include_str!("../../hello.bf"), b"");
+        assert_eq!(output.strip_suffix(HALT), Some(&b"Hello World!\n"[..]));
+    }
+
+    #[test]
+    fn reads_scripted_input() {
+        let output = run(",.,+.", b"ab");
+        assert_eq!(output.strip_suffix(HALT), Some(&b"ac"[..]));
+    }
+
+    #[test]
+    fn halts_when_input_runs_out() {
+        // GETC halts the machine once there are no keys left to read.
+        assert_eq!(run(",[.,]", b"echo"), b"echo");
+    }
+
+    #[test]
+    fn cells_wrap_to_eight_bits() {
+        let output = run("-.+.", b"");
+        assert_eq!(output.strip_suffix(HALT), Some(&[0xff, 0][..]));
+    }
+}
@@ -1,12 +1,17 @@
+mod lc3;
+mod scan;
+
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::stdin;
-use std::io::*;
+use std::path::Path;
+use std::process;
 
-const USAGE_CMD: &'static str = "Welcom to BFF (Brainf*ck Friends) interpreter and compiler.\n
+const USAGE_CMD: &str = "Welcom to BFF (Brainf*ck Friends) interpreter and compiler.\n
 Usage: bff [file] -- Runs a Brainf*ck program from a file.
 Usage: bff jumptable [file] -- Runs a Brainf*ck program using a jumptable.
+Usage: bff compile --target lc3 [file] [-o out.obj] -- Compiles a Brainf*ck program to an LC-3 object image.
 Usage: bff examples -- Runs an example program to print 'Hello World!'.
 ";
 
@@ -23,9 +28,9 @@ fn compute_jumptable(source: &str) -> Vec<usize> {
     let code: Vec<_> = source.chars().collect();
     let sz = code.len();
     // Jump table.
-    let mut jump_table = vec![0 as usize; sz];
+    let mut jump_table = vec![0_usize; sz];
     // Program counter.
-    let mut pc = 0 as usize;
+    let mut pc = 0_usize;
 
     while pc < sz {
         let inst = code[pc];
@@ -33,7 +38,7 @@ fn compute_jumptable(source: &str) -> Vec<usize> {
             let mut bracket_nesting = 1;
             let mut seek = pc;
             while bracket_nesting != 0 && {
-                seek = seek + 1;
+                seek += 1;
                 seek < sz
             } {
                 if code[seek] == ']' {
@@ -54,23 +59,54 @@ fn compute_jumptable(source: &str) -> Vec<usize> {
     jump_table
 }
 
+// Find scan loops such as `[>]` or `[<<<<]`, which only move the pointer by a
+// fixed stride until they reach a zero cell. The stride of each scan is
+// recorded at the position of its opening bracket.
+fn compute_scans(source: &str) -> Vec<Option<isize>> {
+    let code: Vec<_> = source.chars().collect();
+    let mut scans = vec![None; code.len()];
+
+    for (pc, &inst) in code.iter().enumerate() {
+        if inst != '[' {
+            continue;
+        }
+        let mut stride = 0_isize;
+        for &c in &code[pc + 1..] {
+            match c {
+                '>' => stride += 1,
+                '<' => stride -= 1,
+                ']' => {
+                    if stride != 0 {
+                        scans[pc] = Some(stride);
+                    }
+                    break;
+                }
+                '+' | '-' | '.' | ',' | '[' => break,
+                _ => (),
+            }
+        }
+    }
+    scans
+}
+
 pub fn interpret_with_jumptable(source: &str) {
-    assert!(source.len() > 0);
+    assert!(!source.is_empty());
     // Collect chars into a vec so we can do some indexing.
     let code: Vec<_> = source.chars().collect();
     // Tape where we do thingfs.
-    let mut tape = [0 as u8; 30000];
+    let mut tape = [0_u8; 30000];
     // Pointer into the tape.
-    let mut ptr = 0 as usize;
+    let mut ptr = 0_usize;
     // Program counter.
-    let mut pc = 0 as usize;
+    let mut pc = 0_usize;
     // Build jump table.
-    let mut jump_table = compute_jumptable(source);
+    let jump_table = compute_jumptable(source);
+    // Find scan loops.
+    let scans = compute_scans(source);
 
-    // Current character we're processing.
-    let mut curr = code[0];
     while pc < code.len() {
-        curr = code[pc];
+        // Current character we're processing.
+        let curr = code[pc];
         match curr {
             '>' => ptr += 1,
             '<' => ptr -= 1,
@@ -82,18 +118,16 @@ pub fn interpret_with_jumptable(source: &str) {
                 stdin()
                     .read_line(&mut s)
                     .expect("What you wrote isn't text -__-");
-                tape[ptr] = s.chars().nth(0).unwrap() as u8
+                tape[ptr] = s.chars().next().unwrap() as u8
             } // This should read input.
+            '[' if tape[ptr] == 0 => pc = jump_table[pc],
             '[' => {
-                if tape[ptr] == 0 {
-                    pc = jump_table[pc];
-                }
-            }
-            ']' => {
-                if tape[ptr] != 0 {
+                if let Some(stride) = scans[pc] {
+                    ptr = scan::scan(&tape, ptr, stride);
                     pc = jump_table[pc];
                 }
             }
+            ']' if tape[ptr] != 0 => pc = jump_table[pc],
             _ => (),
         }
         pc += 1;
@@ -101,20 +135,19 @@ pub fn interpret_with_jumptable(source: &str) {
 }
 
 pub fn interpret(source: &str) {
-    assert!(source.len() > 0);
+    assert!(!source.is_empty());
     // Collect chars into a vec so we can do some indexing.
     let code: Vec<_> = source.chars().collect();
     // Tape where we do thingfs.
     let mut tape: [u8; 30000] = [0; 30000];
     // Pointer into the tape.
-    let mut ptr = 0 as usize;
+    let mut ptr = 0_usize;
     // Program counter.
-    let mut pc = 0 as usize;
+    let mut pc = 0_usize;
 
-    // Current character we're processing.
-    let mut curr = code[0];
     while pc < code.len() {
-        curr = code[pc];
+        // Current character we're processing.
+        let curr = code[pc];
         match curr {
             '>' => ptr += 1,
             '<' => ptr -= 1,
@@ -126,33 +159,27 @@ pub fn interpret(source: &str) {
                 stdin()
                     .read_line(&mut s)
                     .expect("What you wrote isn't text -__-");
-                tape[ptr] = s.chars().nth(0).unwrap() as u8
+                tape[ptr] = s.chars().next().unwrap() as u8
             } // This should read input.
-            '[' => {
-                if tape[ptr] == 0 {
-                    let mut bracket_nesting = 1;
-                    let saved_pc = pc;
-                    while bracket_nesting != 0 {
-                        if code[pc] == ']' {
-                            bracket_nesting -= 1;
-                        } else if code[pc] == '[' {
-                            bracket_nesting += 1;
-                        }
+            '[' if tape[ptr] == 0 => {
+                let mut bracket_nesting = 1;
+                while bracket_nesting != 0 {
+                    if code[pc] == ']' {
+                        bracket_nesting -= 1;
+                    } else if code[pc] == '[' {
+                        bracket_nesting += 1;
                     }
                 }
             }
-            ']' => {
-                if tape[ptr] != 0 {
-                    let mut bracket_nesting = 1;
-                    let saved_pc = pc;
+            ']' if tape[ptr] != 0 => {
+                let mut bracket_nesting = 1;
 
-                    while bracket_nesting != 0 && pc > 0 {
-                        pc -= 1;
-                        if code[pc] == '[' {
-                            bracket_nesting -= 1;
-                        } else if code[pc] == ']' {
-                            bracket_nesting += 1;
-                        }
+                while bracket_nesting != 0 && pc > 0 {
+                    pc -= 1;
+                    if code[pc] == '[' {
+                        bracket_nesting -= 1;
+                    } else if code[pc] == ']' {
+                        bracket_nesting += 1;
                     }
                 }
             }
@@ -162,12 +189,60 @@ pub fn interpret(source: &str) {
     }
 }
 
+// Compile a Brainf*ck program, only the LC-3 target is supported for now.
+//
+// Usage: bff compile --target lc3 [file] [-o out.obj]
+fn compile(args: &[String]) {
+    let mut target = None;
+    let mut input = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => target = args.next(),
+            "-o" => output = args.next().cloned(),
+            _ => input = Some(arg),
+        }
+    }
+    let Some(input) = input else {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
+    };
+    match target.map(String::as_str) {
+        Some("lc3") => {
+            let image = lc3::compile(&read_file(input));
+            let output = output.unwrap_or_else(|| {
+                Path::new(input)
+                    .with_extension("obj")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            File::create(&output)
+                .and_then(|mut f| f.write_all(&lc3::to_bytes(&image)))
+                .expect("Failed to write object file");
+        }
+        Some(target) => {
+            eprintln!("Unsupported target : {}", target);
+            process::exit(1);
+        }
+        None => {
+            eprintln!("Missing --target");
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     if env::args().len() < 2 {
         println!("{}", USAGE_CMD);
         return;
     }
-    if env::args().nth(1).unwrap() == "examples" {
+    let args: Vec<String> = env::args().collect();
+    if args[1] == "compile" {
+        compile(&args[2..]);
+    } else if args[1] == "jumptable" && args.len() > 2 {
+        interpret_with_jumptable(&read_file(&args[2]));
+    } else if args[1] == "examples" {
         let _test_program = ">>>>++.";
         let _echo_program = "+[>,.,.<]";
         let hello_world = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
@@ -176,7 +251,6 @@ fn main() {
     } else {
         let file_name = env::args().nth(1).unwrap();
         let program = read_file(&file_name);
-        interpret(&program);
-        // interpret_with_jumptable(&program);
+        interpret_with_jumptable(&program);
     }
 }
@@ -0,0 +1,255 @@
+// Vectorized execution of scan loops such as `[>]` or `[<<<<]`.
+//
+// A scan loop moves the tape pointer by a fixed stride until it lands on a
+// zero cell. Strides 1, 2 and 4 divide the vector width so we can compare a
+// whole chunk of the tape against zero and mask out the lanes that aren't
+// visited by the loop. Other strides use the scalar loop.
+
+// Lane bits visited by a forward scan, bit `b` is set if `b % stride == 0`.
+fn stride_mask(stride: usize) -> u32 {
+    match stride {
+        1 => 0xffff_ffff,
+        2 => 0x5555_5555,
+        4 => 0x1111_1111,
+        _ => unreachable!(),
+    }
+}
+
+// Returns the position of the first zero cell in `ptr, ptr + stride, ...`.
+pub fn scan(tape: &[u8], ptr: usize, stride: isize) -> usize {
+    let step = stride.unsigned_abs();
+    if matches!(step, 1 | 2 | 4) {
+        scan_vector(tape, ptr, stride)
+    } else {
+        scan_scalar(tape, ptr, stride)
+    }
+}
+
+// Scalar fallback, also used to finish scans close to the tape edges.
+fn scan_scalar(tape: &[u8], mut ptr: usize, stride: isize) -> usize {
+    while tape[ptr] != 0 {
+        ptr = ptr
+            .checked_add_signed(stride)
+            .expect("Scan moved the pointer off the tape");
+    }
+    ptr
+}
+
+// Vector scan, picks the widest instruction set available at runtime.
+#[cfg(target_arch = "x86_64")]
+fn scan_vector(tape: &[u8], ptr: usize, stride: isize) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was checked above.
+        unsafe { x86::scan_avx2(tape, ptr, stride) }
+    } else {
+        // SAFETY: SSE2 is part of the x86-64 baseline.
+        unsafe { x86::scan_sse2(tape, ptr, stride) }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn scan_vector(tape: &[u8], ptr: usize, stride: isize) -> usize {
+    // SAFETY: NEON is part of the AArch64 baseline.
+    unsafe { neon::scan(tape, ptr, stride) }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn scan_vector(tape: &[u8], ptr: usize, stride: isize) -> usize {
+    scan_scalar(tape, ptr, stride)
+}
+
+// Walks the tape one chunk of `width` cells at a time. `zeros` returns a
+// bitmask with bit `b` set if the cell at `base + b` is zero, where `base` is
+// the first cell of the chunk. Cells that don't fill a whole chunk at the
+// tape edges are finished with the scalar loop.
+//
+// Forward scans start chunks at `ptr` so the visited lanes are the ones in
+// `stride_mask`. Backward scans end chunks at `ptr` so the visited lanes are
+// shifted up by `stride - 1`.
+fn scan_chunks(
+    tape: &[u8],
+    ptr: usize,
+    stride: isize,
+    width: usize,
+    zeros: impl Fn(usize) -> u32,
+) -> usize {
+    let step = stride.unsigned_abs();
+    let lanes = if width == 32 {
+        u32::MAX
+    } else {
+        (1 << width) - 1
+    };
+    if stride > 0 {
+        let mask = stride_mask(step) & lanes;
+        let mut base = ptr;
+        while base + width <= tape.len() {
+            let hits = zeros(base) & mask;
+            if hits != 0 {
+                return base + hits.trailing_zeros() as usize;
+            }
+            base += width;
+        }
+        // Pick up the tail from the first cell of the next chunk.
+        scan_scalar(tape, base, stride)
+    } else {
+        let mask = (stride_mask(step) << (step - 1)) & lanes;
+        let mut end = ptr + 1;
+        while end >= width {
+            let base = end - width;
+            let hits = zeros(base) & mask;
+            if hits != 0 {
+                return base + 31 - hits.leading_zeros() as usize;
+            }
+            end = base;
+        }
+        if end == 0 {
+            panic!("Scan moved the pointer off the tape");
+        }
+        scan_scalar(tape, end - 1, stride)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::scan_chunks;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn scan_sse2(tape: &[u8], ptr: usize, stride: isize) -> usize {
+        let zero = _mm_setzero_si128();
+        scan_chunks(tape, ptr, stride, 16, |base| {
+            // SAFETY: `scan_chunks` only passes bases with 16 cells left.
+            let chunk = unsafe { _mm_loadu_si128(tape.as_ptr().add(base) as *const __m128i) };
+            _mm_movemask_epi8(_mm_cmpeq_epi8(chunk, zero)) as u32
+        })
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn scan_avx2(tape: &[u8], ptr: usize, stride: isize) -> usize {
+        let zero = _mm256_setzero_si256();
+        scan_chunks(tape, ptr, stride, 32, |base| {
+            // SAFETY: `scan_chunks` only passes bases with 32 cells left.
+            let chunk = unsafe { _mm256_loadu_si256(tape.as_ptr().add(base) as *const __m256i) };
+            _mm256_movemask_epi8(_mm256_cmpeq_epi8(chunk, zero)) as u32
+        })
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::scan_chunks;
+    use std::arch::aarch64::*;
+
+    // NEON has no movemask, narrow the comparison result to one nibble per
+    // lane and keep the low bit of each nibble.
+    pub unsafe fn scan(tape: &[u8], ptr: usize, stride: isize) -> usize {
+        scan_chunks(tape, ptr, stride, 16, |base| {
+            // SAFETY: `scan_chunks` only passes bases with 16 cells left.
+            let nibbles = unsafe {
+                let chunk = vld1q_u8(tape.as_ptr().add(base));
+                let eq = vceqq_u8(chunk, vdupq_n_u8(0));
+                let narrowed = vshrn_n_u16(vreinterpretq_u16_u8(eq), 4);
+                vget_lane_u64(vreinterpret_u64_u8(narrowed), 0)
+            };
+            let mut mask = 0;
+            for lane in 0..16 {
+                if (nibbles >> (lane * 4)) & 1 != 0 {
+                    mask |= 1 << lane;
+                }
+            }
+            mask
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tape of ones with zeros at the given positions.
+    fn tape(len: usize, zeros: &[usize]) -> Vec<u8> {
+        let mut tape = vec![1; len];
+        for &zero in zeros {
+            tape[zero] = 0;
+        }
+        tape
+    }
+
+    // Check every vector scan against the scalar loop.
+    fn check(tape: &[u8], ptr: usize, stride: isize) {
+        let expected = scan_scalar(tape, ptr, stride);
+        assert_eq!(scan(tape, ptr, stride), expected, "{} {}", ptr, stride);
+        #[cfg(target_arch = "x86_64")]
+        if matches!(stride.unsigned_abs(), 1 | 2 | 4) {
+            // SAFETY: SSE2 is part of the x86-64 baseline.
+            let sse2 = unsafe { x86::scan_sse2(tape, ptr, stride) };
+            assert_eq!(sse2, expected, "sse2 {} {}", ptr, stride);
+        }
+    }
+
+    #[test]
+    fn matches_scalar_scan() {
+        // Every distance up to a few chunks from a handful of alignments.
+        // Cells the scan steps over are zero so unmasked lanes would stop it.
+        for len in [200_usize, 203] {
+            for stride in [1_isize, 2, 3, 4, -1, -2, -3, -4] {
+                for ptr in [0_usize, 1, 7, 16, 32, 99, 199] {
+                    let visited = |cell: usize| (cell as isize - ptr as isize) % stride == 0;
+                    let mut target = Some(ptr);
+                    while let Some(zero) = target.filter(|&zero| zero < len) {
+                        let tape: Vec<u8> = (0..len)
+                            .map(|cell| (visited(cell) && cell != zero) as u8)
+                            .collect();
+                        check(&tape, ptr, stride);
+                        target = zero.checked_add_signed(stride);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zero_in_first_chunk() {
+        for stride in [1, 2, 3, 4] {
+            check(&tape(100, &[12]), 0, stride);
+            check(&tape(100, &[80]), 80 + 3 * stride as usize, -stride);
+        }
+    }
+
+    #[test]
+    fn zero_on_chunk_boundary() {
+        for stride in [1, 2, 4] {
+            for boundary in [16, 32, 64] {
+                assert_eq!(scan(&tape(100, &[boundary]), 0, stride), boundary);
+                let ptr = 80;
+                let zero = ptr - boundary;
+                assert_eq!(scan(&tape(100, &[zero]), ptr, -stride), zero);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_in_scalar_tail() {
+        // 100 cells hold 3 full 32-cell or 6 full 16-cell chunks from 0.
+        for stride in [1, 2, 3, 4] {
+            let zero = 99 / stride * stride;
+            assert_eq!(scan(&tape(100, &[zero]), 0, stride as isize), zero);
+        }
+    }
+
+    #[test]
+    fn backward_scan_to_tape_start() {
+        for stride in [1, 2, 3, 4] {
+            for ptr in [0, 12, 15, 16, 31, 32, 48, 96] {
+                let ptr = ptr / stride * stride;
+                assert_eq!(scan(&tape(100, &[0]), ptr, -(stride as isize)), 0);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "off the tape")]
+    fn backward_scan_off_the_tape() {
+        scan(&tape(100, &[]), 50, -1);
+    }
+}
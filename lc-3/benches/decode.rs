@@ -0,0 +1,91 @@
+// Compares the interpreter with and without the decoded instruction cache on
+// a compute heavy program. Run with `cargo bench`.
+//
+// Both modes dispatch on the decoded `Instruction`, the uncached one decodes
+// it again every step. This measures what the cache saves over decoding,
+// not how either compares to the handlers that extracted their own fields
+// from the raw word before the cache was added.
+use std::time::{Duration, Instant};
+
+use lc_3::asm;
+use lc_3::console::ScriptedConsole;
+use lc_3::vm::{Register, VirtualMachine};
+
+// Repeatedly sums an array while rewriting it.
+const PROGRAM: &str = "
+        .ORIG x3000
+        LD R5, PASSES
+PASS    LEA R1, ARRAY
+        LD R2, LENGTH
+        AND R0, R0, #0
+SUM     LDR R3, R1, #0
+        ADD R0, R0, R3
+        NOT R4, R3
+        AND R4, R4, #15
+        ADD R4, R4, R0
+        STR R4, R1, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp SUM
+        ADD R5, R5, #-1
+        BRp PASS
+        HALT
+PASSES  .FILL #2000
+LENGTH  .FILL #200
+ARRAY   .BLKW #200
+        .END
+";
+
+const RUNS: usize = 20;
+
+// Run the program to completion, returns the instructions executed and the
+// time taken.
+fn run(object: &[u8], cached: bool) -> (u64, Duration) {
+    let console = ScriptedConsole::new(b"");
+    let mut vm = VirtualMachine::with_console(Box::new(console));
+    vm.load_image("bench", object).expect("program should load");
+    vm.registers[Register::Pc as usize] = 0x3000;
+    if !cached {
+        vm.decoded = None;
+    }
+    let mut steps = 0;
+    let start = Instant::now();
+    while !vm.halted {
+        vm.step();
+        steps += 1;
+    }
+    (steps, start.elapsed())
+}
+
+fn main() {
+    let object = asm::assemble(PROGRAM)
+        .expect("benchmark program should assemble")
+        .to_obj();
+    // Alternate between both modes so changes in machine load affect them
+    // alike, keeping the fastest run of each.
+    let mut steps = 0;
+    let mut decoding = Duration::MAX;
+    let mut cached = Duration::MAX;
+    for _ in 0..RUNS {
+        let (n, time) = run(&object, false);
+        steps = n;
+        decoding = decoding.min(time);
+        cached = cached.min(run(&object, true).1);
+    }
+    let mips = |time: Duration| steps as f64 / time.as_secs_f64() / 1e6;
+    println!("{} instructions, best of {} runs", steps, RUNS);
+    println!(
+        "decode every step  {:>10.3?}  {:>7.1} MIPS",
+        decoding,
+        mips(decoding)
+    );
+    println!(
+        "decoded cache      {:>10.3?}  {:>7.1} MIPS",
+        cached,
+        mips(cached)
+    );
+    println!(
+        "speedup            {:>10.2}x",
+        decoding.as_secs_f64() / cached.as_secs_f64()
+    );
+}
@@ -0,0 +1,760 @@
+// Two-pass assembler for the LC-3 assembly language.
+//
+// The first pass splits lines into labels, mnemonics and operands, assigns
+// an address to every statement and records labels in the symbol table. The
+// second pass encodes statements now that every label is known.
+//
+// A program is a single `.ORIG` block ended by `.END`, which maps to a single
+// object image.
+use std::fmt;
+
+use crate::lines::LineTable;
+use crate::symbols::SymbolTable;
+
+// Assembled program.
+#[derive(Clone, Debug)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    // Source lines of instructions, the source path is left for the caller
+    // to fill in.
+    pub lines: LineTable,
+}
+
+impl Assembly {
+    // Serialize to the big-endian object file format, origin first.
+    pub fn to_obj(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+}
+
+// Error at a given source line, lines are numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} : {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Reg(u16),
+    Num(i32),
+    Label(String),
+    Str(String),
+}
+
+// A single instruction or directive.
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    address: u16,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+// Trap aliases and their vectors.
+const TRAP_ALIASES: [(&str, u16); 6] = [
+    ("GETC", 0x20),
+    ("OUT", 0x21),
+    ("PUTS", 0x22),
+    ("IN", 0x23),
+    ("PUTSP", 0x24),
+    ("HALT", 0x25),
+];
+
+const MNEMONICS: [&str; 21] = [
+    "ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR",
+    "LEA", "ST", "STI", "STR", "TRAP", "RTI", ".ORIG", ".FILL", ".BLKW",
+    ".STRINGZ", ".END",
+];
+
+// Condition bits of a BR mnemonic, `BR` alone branches always.
+fn branch_cond(mnemonic: &str) -> Option<u16> {
+    let flags = mnemonic.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+    let mut cond = 0;
+    let mut last = 0;
+    for c in flags.chars() {
+        let (bit, order) = match c {
+            'N' => (0b100, 1),
+            'Z' => (0b010, 2),
+            'P' => (0b001, 3),
+            _ => return None,
+        };
+        if order <= last {
+            return None;
+        }
+        last = order;
+        cond |= bit;
+    }
+    Some(cond)
+}
+
+fn is_mnemonic(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    MNEMONICS.contains(&upper.as_str())
+        || TRAP_ALIASES.iter().any(|(alias, _)| *alias == upper)
+        || branch_cond(&upper).is_some()
+}
+
+// Registers are written as `R0` to `R7`, in either case.
+pub fn parse_register(token: &str) -> Option<u16> {
+    let bytes = token.as_bytes();
+    if bytes.len() == 2
+        && (bytes[0] == b'R' || bytes[0] == b'r')
+        && (b'0'..=b'7').contains(&bytes[1])
+    {
+        Some((bytes[1] - b'0') as u16)
+    } else {
+        None
+    }
+}
+
+// Numbers are written as `#10`, `#-3`, `x3000`, `x-1` or plain decimals.
+pub fn parse_number(token: &str) -> Option<i32> {
+    let (digits, radix) = if let Some(rest) = token.strip_prefix('#') {
+        (rest, 10)
+    } else if let Some(rest) =
+        token.strip_prefix('x').or_else(|| token.strip_prefix('X'))
+    {
+        (rest, 16)
+    } else {
+        (token, 10)
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+fn is_label(token: &str) -> bool {
+    let mut chars = token.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(token).is_none()
+        && parse_number(token).is_none()
+}
+
+// Split a line into tokens, dropping comments. Commas separate operands
+// like whitespace does and string literals are kept whole.
+fn tokenize(text: &str) -> Result<Vec<Operand>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() || c == ',' {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('0') => s.push('\0'),
+                        Some('e') => s.push('\x1b'),
+                        Some(c @ ('"' | '\\')) => s.push(c),
+                        Some(c) => {
+                            return Err(format!("unknown escape '\\{}'", c))
+                        }
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Operand::Str(s));
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(if let Some(reg) = parse_register(&token) {
+                Operand::Reg(reg)
+            } else if let Some(num) = parse_number(&token) {
+                Operand::Num(num)
+            } else {
+                Operand::Label(token)
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+// Number of words a statement occupies.
+fn size(statement: &Statement) -> Result<u32, String> {
+    match statement.mnemonic.as_str() {
+        ".BLKW" => match statement.operands.as_slice() {
+            [Operand::Num(n)] if *n >= 0 => Ok(*n as u32),
+            _ => Err(".BLKW expects a positive word count".to_string()),
+        },
+        ".STRINGZ" => match statement.operands.as_slice() {
+            [Operand::Str(s)] => Ok(s.chars().count() as u32 + 1),
+            _ => Err(".STRINGZ expects a string".to_string()),
+        },
+        _ => Ok(1),
+    }
+}
+
+// Check that a value fits in a signed field of `bits` bits and mask it.
+fn signed_field(value: i32, bits: u32, what: &str) -> Result<u16, String> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(format!(
+            "{} {} is out of range ({}..{})",
+            what, value, min, max
+        ));
+    }
+    Ok(value as u16 & ((1 << bits) - 1))
+}
+
+struct Encoder<'a> {
+    symbols: &'a SymbolTable,
+}
+
+impl Encoder<'_> {
+    // Resolve a label or literal to a PC-relative offset of `bits` bits.
+    fn pc_offset(
+        &self,
+        operand: &Operand,
+        address: u16,
+        bits: u32,
+    ) -> Result<u16, String> {
+        match operand {
+            Operand::Label(name) => {
+                let target = self
+                    .symbols
+                    .get(name)
+                    .ok_or_else(|| format!("undefined label '{}'", name))?;
+                let offset = target as i32 - (address as i32 + 1);
+                signed_field(offset, bits, &format!("PC offset to '{}'", name))
+            }
+            Operand::Num(n) => signed_field(*n, bits, "PC offset"),
+            _ => Err("expected a label or offset".to_string()),
+        }
+    }
+
+    fn encode(&self, statement: &Statement) -> Result<Vec<u16>, String> {
+        use Operand::*;
+        let address = statement.address;
+        let m = statement.mnemonic.as_str();
+        let ops = statement.operands.as_slice();
+        let word = match (m, ops) {
+            ("ADD" | "AND", [Reg(dr), Reg(sr1), second]) => {
+                let op = if m == "ADD" { 0b0001 } else { 0b0101 };
+                let base = (op << 12) | (dr << 9) | (sr1 << 6);
+                match second {
+                    Reg(sr2) => base | sr2,
+                    Num(n) => {
+                        base | (1 << 5) | signed_field(*n, 5, "immediate")?
+                    }
+                    _ => return Err(format!("bad operands for {}", m)),
+                }
+            }
+            ("NOT", [Reg(dr), Reg(sr)]) => {
+                (0b1001 << 12) | (dr << 9) | (sr << 6) | 0x3f
+            }
+            ("JMP", [Reg(base)]) => (0b1100 << 12) | (base << 6),
+            ("RET", []) => (0b1100 << 12) | (7 << 6),
+            ("JSR", [target]) => {
+                (0b0100 << 12)
+                    | (1 << 11)
+                    | self.pc_offset(target, address, 11)?
+            }
+            ("JSRR", [Reg(base)]) => (0b0100 << 12) | (base << 6),
+            ("LD" | "LDI" | "LEA" | "ST" | "STI", [Reg(r), target]) => {
+                let op = match m {
+                    "LD" => 0b0010,
+                    "LDI" => 0b1010,
+                    "LEA" => 0b1110,
+                    "ST" => 0b0011,
+                    _ => 0b1011,
+                };
+                (op << 12) | (r << 9) | self.pc_offset(target, address, 9)?
+            }
+            ("LDR" | "STR", [Reg(r), Reg(base), Num(offset)]) => {
+                let op = if m == "LDR" { 0b0110 } else { 0b0111 };
+                (op << 12)
+                    | (r << 9)
+                    | (base << 6)
+                    | signed_field(*offset, 6, "offset")?
+            }
+            ("TRAP", [Num(vector)]) => {
+                if !(0..=0xff).contains(vector) {
+                    return Err(format!(
+                        "trap vector {} is out of range",
+                        vector
+                    ));
+                }
+                (0b1111 << 12) | *vector as u16
+            }
+            ("RTI", []) => 0b1000 << 12,
+            (".FILL", [value]) => match value {
+                Num(n) if (-0x8000..=0xffff).contains(n) => *n as u16,
+                Num(n) => {
+                    return Err(format!(".FILL value {} is out of range", n))
+                }
+                Label(name) => self
+                    .symbols
+                    .get(name)
+                    .ok_or_else(|| format!("undefined label '{}'", name))?,
+                _ => return Err(".FILL expects a number or label".to_string()),
+            },
+            (".BLKW", [Num(n)]) => return Ok(vec![0; *n as usize]),
+            (".STRINGZ", [Str(s)]) => {
+                let mut words: Vec<u16> = s.chars().map(|c| c as u16).collect();
+                words.push(0);
+                return Ok(words);
+            }
+            _ => {
+                if let Some((_, vector)) =
+                    TRAP_ALIASES.iter().find(|(alias, _)| *alias == m)
+                {
+                    if !ops.is_empty() {
+                        return Err(format!("{} takes no operands", m));
+                    }
+                    (0b1111 << 12) | vector
+                } else if let Some(cond) = branch_cond(m) {
+                    match ops {
+                        [target] => {
+                            (cond << 9) | self.pc_offset(target, address, 9)?
+                        }
+                        _ => return Err(format!("bad operands for {}", m)),
+                    }
+                } else {
+                    return Err(format!("bad operands for {}", m));
+                }
+            }
+        };
+        Ok(vec![word])
+    }
+}
+
+// Assemble a program, all errors found are reported sorted by line.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    let mut errors = vec![];
+    let mut symbols = SymbolTable::new();
+    let mut statements = vec![];
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut ended = false;
+
+    // First pass, parse lines and assign addresses.
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut error =
+            |message: String| errors.push(AsmError { line, message });
+        let mut tokens = match tokenize(text) {
+            Ok(tokens) => tokens,
+            Err(message) => {
+                error(message);
+                continue;
+            }
+        };
+        if tokens.is_empty() {
+            continue;
+        }
+        // A leading token that isn't a mnemonic is a label.
+        let label = match &tokens[0] {
+            Operand::Label(name) if !is_mnemonic(name) => Some(name.clone()),
+            _ => None,
+        };
+        if let Some(label) = &label {
+            tokens.remove(0);
+            if !is_label(label) {
+                error(format!("invalid label '{}'", label));
+                continue;
+            }
+        }
+        let mnemonic = match tokens.first() {
+            Some(Operand::Label(name)) if is_mnemonic(name) => {
+                name.to_ascii_uppercase()
+            }
+            Some(Operand::Label(name)) => {
+                error(format!("unknown instruction '{}'", name));
+                continue;
+            }
+            Some(_) => {
+                error(match &label {
+                    Some(label) => format!("unknown instruction '{}'", label),
+                    None => "expected an instruction or directive".to_string(),
+                });
+                continue;
+            }
+            None => String::new(),
+        };
+        if mnemonic == ".END" {
+            ended = true;
+            break;
+        }
+        if mnemonic == ".ORIG" {
+            match (origin, tokens.get(1)) {
+                (Some(_), _) => error("multiple .ORIG blocks".to_string()),
+                (None, Some(Operand::Num(n))) if (0..=0xffff).contains(n) => {
+                    origin = Some(*n as u16);
+                    address = *n as u32;
+                    // A label on the .ORIG line names the origin.
+                    if let Some(label) = &label {
+                        symbols.insert(label, *n as u16);
+                    }
+                }
+                _ => error(".ORIG expects an address".to_string()),
+            }
+            continue;
+        }
+        if origin.is_none() {
+            error("code before .ORIG".to_string());
+            continue;
+        }
+        if let Some(label) = &label {
+            if !symbols.insert(label, address as u16) {
+                error(format!("duplicate label '{}'", label));
+            }
+        }
+        if mnemonic.is_empty() {
+            continue;
+        }
+        let statement = Statement {
+            line,
+            address: address as u16,
+            mnemonic,
+            operands: tokens.split_off(1),
+        };
+        match size(&statement) {
+            Ok(size) => address += size,
+            Err(message) => {
+                error(message);
+                continue;
+            }
+        }
+        if address > 0x10000 {
+            error("program runs past the end of memory".to_string());
+            ended = true;
+            break;
+        }
+        statements.push(statement);
+    }
+    let Some(origin) = origin else {
+        errors.push(AsmError {
+            line: source.lines().count().max(1),
+            message: "missing .ORIG".to_string(),
+        });
+        return Err(errors);
+    };
+    if !ended {
+        errors.push(AsmError {
+            line: source.lines().count().max(1),
+            message: "missing .END".to_string(),
+        });
+    }
+
+    // Second pass, encode statements.
+    let encoder = Encoder { symbols: &symbols };
+    let mut words = vec![];
+    let mut lines = LineTable::new();
+    for statement in &statements {
+        if !statement.mnemonic.starts_with('.') {
+            lines.insert(statement.address, statement.line);
+        }
+        match encoder.encode(statement) {
+            Ok(encoded) => words.extend(encoded),
+            Err(message) => errors.push(AsmError {
+                line: statement.line,
+                message,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Assembly {
+            origin,
+            words,
+            symbols,
+            lines,
+        })
+    } else {
+        errors.sort_by_key(|err| err.line);
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source).unwrap().words
+    }
+
+    // Errors reported for a program as `line : message` strings.
+    fn errors(source: &str) -> Vec<String> {
+        match assemble(source) {
+            Ok(_) => panic!("assembled"),
+            Err(errors) => errors.iter().map(|err| err.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn instructions() {
+        let program = assemble(
+            "        .ORIG x3000
+START   ADD R1, R2, R3
+        ADD R1, R2, #-1
+        AND R1, R2, R3
+        and r0, r0, #0
+        NOT R1, R2
+        JMP R3
+        RET
+        JSR START
+        JSRR R4
+        LD R0, DATA
+        LDI R1, DATA
+        LDR R1, R2, #-2
+        LEA R2, DATA
+        ST R3, DATA
+        STI R4, DATA
+        STR R3, R4, #5
+        TRAP x25
+        RTI
+        BRnzp START
+        BRz DATA
+        BR #0
+        BRnp x-1
+DATA    .FILL x-1
+        .END",
+        )
+        .unwrap();
+        assert_eq!(program.origin, 0x3000);
+        assert_eq!(
+            program.words,
+            [
+                0x1283, 0x12BF, 0x5283, 0x5020, 0x92BF, 0xC0C0, 0xC1C0, 0x4FF8,
+                0x4100, 0x200C, 0xA20B, 0x62BE, 0xE409, 0x3608, 0xB807, 0x7705,
+                0xF025, 0x8000, 0x0FED, 0x0402, 0x0E00, 0x0BFF, 0xFFFF,
+            ]
+        );
+        assert_eq!(program.symbols.get("START"), Some(0x3000));
+        assert_eq!(program.symbols.get("DATA"), Some(0x3016));
+        // Only instructions have a line, not data.
+        assert_eq!(program.lines.line(0x3000), Some(2));
+        assert_eq!(program.lines.line(0x3015), Some(23));
+        assert_eq!(program.lines.line(0x3016), None);
+    }
+
+    #[test]
+    fn trap_aliases() {
+        assert_eq!(
+            words(".ORIG x3000\nGETC\nOUT\nPUTS\nIN\nPUTSP\nhalt\n.END"),
+            [0xF020, 0xF021, 0xF022, 0xF023, 0xF024, 0xF025]
+        );
+        assert_eq!(
+            errors(".ORIG x3000\nHALT R0\n.END"),
+            ["line 2 : HALT takes no operands"]
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let program = assemble(
+            "        .ORIG x4000
+        .FILL #-32768
+        .FILL 65535
+        .FILL END
+        .BLKW #3
+TEXT    .STRINGZ \"a\\n\\\"\"
+        .BLKW 0
+END     .STRINGZ \"\"
+        .END",
+        )
+        .unwrap();
+        assert_eq!(
+            program.words,
+            [0x8000, 0xFFFF, 0x400A, 0, 0, 0, 0x61, 0x0A, 0x22, 0, 0]
+        );
+        assert_eq!(program.symbols.get("TEXT"), Some(0x4006));
+        assert_eq!(program.symbols.get("END"), Some(0x400A));
+        assert_eq!(program.lines.iter().count(), 0);
+        assert_eq!(
+            program.to_obj(),
+            [
+                0x40, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0x40, 0x0A, 0, 0, 0, 0, 0,
+                0, 0, 0x61, 0, 0x0A, 0, 0x22, 0, 0, 0, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn directive_errors() {
+        assert_eq!(
+            errors(
+                ".ORIG x3000\n.FILL #65536\n.FILL #-32769\n.BLKW #-1\n\
+                 .STRINGZ 1\n.FILL \"a\"\n.END"
+            ),
+            [
+                "line 2 : .FILL value 65536 is out of range",
+                "line 3 : .FILL value -32769 is out of range",
+                "line 4 : .BLKW expects a positive word count",
+                "line 5 : .STRINGZ expects a string",
+                "line 6 : .FILL expects a number or label",
+            ]
+        );
+    }
+
+    #[test]
+    fn label_on_orig() {
+        let program =
+            assemble("MAIN .ORIG x3000\nBR MAIN\nHALT\n.END").unwrap();
+        assert_eq!(program.symbols.get("MAIN"), Some(0x3000));
+        assert_eq!(program.words, [0x0FFF, 0xF025]);
+    }
+
+    #[test]
+    fn missing_orig_and_end() {
+        assert_eq!(
+            errors("HALT\n.END"),
+            ["line 1 : code before .ORIG", "line 2 : missing .ORIG"]
+        );
+        assert_eq!(errors(".ORIG x3000\nHALT\n"), ["line 2 : missing .END"]);
+        assert_eq!(
+            errors(".ORIG x3000\n.ORIG x4000\n.END"),
+            ["line 2 : multiple .ORIG blocks"]
+        );
+        // Lines after .END are ignored.
+        assert_eq!(words(".ORIG x3000\nHALT\n.END\nnot assembled"), [0xF025]);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            errors(
+                ".ORIG x3000\nLOOP ADD R0, R0, #1\nLOOP BR LOOP\n\
+                 LD R0, NOWHERE\n.FILL ELSEWHERE\n.END"
+            ),
+            [
+                "line 3 : duplicate label 'LOOP'",
+                "line 4 : undefined label 'NOWHERE'",
+                "line 5 : undefined label 'ELSEWHERE'",
+            ]
+        );
+        assert_eq!(
+            errors(".ORIG x3000\n1ABC HALT\nFOO BAR\n.END"),
+            [
+                "line 2 : invalid label '1ABC'",
+                "line 3 : unknown instruction 'BAR'",
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(
+            errors(
+                "        .ORIG x3000
+        ADD R0, R0, #15
+        ADD R0, R0, #16
+        AND R0, R0, #-17
+        LDR R0, R1, #32
+        STR R0, R1, #-33
+        BR FAR
+        LD R0, FAR
+        JSR FAR
+        BR #-257
+        TRAP x100
+        .BLKW #1000
+FAR     .FILL #0
+        .END"
+            ),
+            [
+                "line 3 : immediate 16 is out of range (-16..15)",
+                "line 4 : immediate -17 is out of range (-16..15)",
+                "line 5 : offset 32 is out of range (-32..31)",
+                "line 6 : offset -33 is out of range (-32..31)",
+                "line 7 : PC offset to 'FAR' 1004 is out of range (-256..255)",
+                "line 8 : PC offset to 'FAR' 1003 is out of range (-256..255)",
+                "line 10 : PC offset -257 is out of range (-256..255)",
+                "line 11 : trap vector 256 is out of range",
+            ]
+        );
+        assert_eq!(
+            errors(".ORIG xFFFE\n.BLKW #3\n.END"),
+            ["line 2 : program runs past the end of memory"]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            errors(
+                ".ORIG x3000\n.STRINGZ \"open\n.STRINGZ \"\\q\"\n\
+                 ADD R0, R0\nNOT R0, #1\n.END"
+            ),
+            [
+                "line 2 : unterminated string",
+                "line 3 : unknown escape '\\q'",
+                "line 4 : bad operands for ADD",
+                "line 5 : bad operands for NOT",
+            ]
+        );
+    }
+
+    #[test]
+    fn sym_output() {
+        let program = assemble(
+            ".ORIG x3000\nLOOP BR LOOP\nDATA .FILL #1\nA_LONGER_LABEL_NAME \
+             .FILL #2\n.END",
+        )
+        .unwrap();
+        assert_eq!(
+            program.symbols.to_sym(),
+            "// Symbol table\n\
+             // Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n\
+             //\tLOOP              3000\n\
+             //\tDATA              3001\n\
+             //\tA_LONGER_LABEL_NAME  3002\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn numbers_and_registers() {
+        assert_eq!(parse_number("#10"), Some(10));
+        assert_eq!(parse_number("#-3"), Some(-3));
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("X-1"), Some(-1));
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("x"), None);
+        assert_eq!(parse_number("#1a"), None);
+        assert_eq!(parse_register("r7"), Some(7));
+        assert_eq!(parse_register("R8"), None);
+    }
+}
@@ -0,0 +1,211 @@
+// Console devices used by the trap routines to read from the keyboard and
+// write to the display.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+pub trait Console: fmt::Debug {
+    // Block until a key is typed, returns `None` once input is exhausted.
+    fn read(&mut self) -> Option<u8>;
+    // Return a key if one was typed, without blocking.
+    fn poll(&mut self) -> Option<u8>;
+    // Write a character to the display.
+    fn write(&mut self, c: u8);
+    // Flush characters that were written but not displayed yet.
+    fn flush(&mut self) {}
+    // Whether input ended, once it has no key will ever be typed.
+    fn closed(&self) -> bool {
+        false
+    }
+}
+
+// Console backed by the process standard input and output.
+//
+// Standard input is read by a background thread, started on first use, so
+// keys can be polled without blocking.
+#[derive(Debug, Default)]
+pub struct StdConsole {
+    keys: Option<Receiver<u8>>,
+    // Set once standard input was read to its end.
+    closed: bool,
+}
+
+impl StdConsole {
+    fn keys(&mut self) -> &Receiver<u8> {
+        self.keys.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(byte) if sender.send(byte).is_ok() => (),
+                        _ => break,
+                    }
+                }
+            });
+            receiver
+        })
+    }
+}
+
+impl Console for StdConsole {
+    fn read(&mut self) -> Option<u8> {
+        self.flush();
+        let key = self.keys().recv().ok();
+        self.closed = key.is_none();
+        key
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.flush();
+        match self.keys().try_recv() {
+            Ok(key) => Some(key),
+            Err(err) => {
+                self.closed = err == TryRecvError::Disconnected;
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, c: u8) {
+        io::stdout()
+            .write_all(&[c])
+            .expect("Failed to write to stdout");
+    }
+
+    fn flush(&mut self) {
+        io::stdout().flush().expect("Failed to flush stdout");
+    }
+
+    fn closed(&self) -> bool {
+        self.closed
+    }
+}
+
+// Console reading keys from a script and capturing everything displayed.
+//
+// Clones share the same buffers, so a test can keep a clone around to
+// inspect the display after handing the console to the VM.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedConsole {
+    input: Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl ScriptedConsole {
+    // Create a console that will type `input` on the keyboard.
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: Rc::new(RefCell::new(input.iter().copied().collect())),
+            output: Rc::default(),
+        }
+    }
+
+    // Everything written to the display so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn read(&mut self) -> Option<u8> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.read()
+    }
+
+    fn write(&mut self, c: u8) {
+        self.output.borrow_mut().push(c)
+    }
+
+    fn closed(&self) -> bool {
+        self.input.borrow().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::{Register, RunResult, VirtualMachine};
+
+    // Printed by the HALT routine.
+    const HALT: &[u8] = b"\n----- Halting the processor ----- \n";
+
+    // Assemble and run a program at x3000 with the given keyboard input,
+    // returns the machine and everything it displayed.
+    fn run(source: &str, input: &[u8]) -> (VirtualMachine, Vec<u8>) {
+        let program = assemble(source).expect("program assembles");
+        let console = ScriptedConsole::new(input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = program.origin;
+        assert_eq!(vm.run(), RunResult::Halted);
+        (vm, console.output())
+    }
+
+    #[test]
+    fn getc_reads_without_echo() {
+        let (vm, output) = run(".ORIG x3000\nGETC\nHALT\n.END", b"xy");
+        assert_eq!(vm.registers[Register::R0 as usize], b'x' as u16);
+        assert_eq!(output, HALT);
+    }
+
+    #[test]
+    fn out_writes_low_byte() {
+        let source = ".ORIG x3000\nLD R0, C\nOUT\nHALT\nC .FILL x0141\n.END";
+        let (_, output) = run(source, b"");
+        assert_eq!(output, [b"A", HALT].concat());
+    }
+
+    #[test]
+    fn puts_writes_until_zero() {
+        let source =
+            ".ORIG x3000\nLEA R0, S\nPUTS\nHALT\nS .STRINGZ \"hi\\n\"\n.END";
+        let (_, output) = run(source, b"");
+        assert_eq!(output, [b"hi\n", HALT].concat());
+    }
+
+    #[test]
+    fn in_prompts_and_echoes() {
+        let (vm, output) = run(".ORIG x3000\nIN\nHALT\n.END", b"k");
+        assert_eq!(vm.registers[Register::R0 as usize], b'k' as u16);
+        assert_eq!(output, [b"\nInput a character>k\n", HALT].concat());
+    }
+
+    #[test]
+    fn putsp_writes_two_characters_per_word() {
+        // "hi!" has an odd length, the last word has a zero high byte which
+        // isn't displayed.
+        let source = ".ORIG x3000\nLEA R0, S\nPUTSP\nHALT\n\
+                      S .FILL x6968\n.FILL x0021\n.FILL 0\n.END";
+        let (_, output) = run(source, b"");
+        assert_eq!(output, [b"hi!", HALT].concat());
+    }
+
+    #[test]
+    fn halt_stops_the_machine() {
+        let source = ".ORIG x3000\nHALT\nLD R0, C\nOUT\nC .FILL x41\n.END";
+        let (vm, output) = run(source, b"");
+        assert!(vm.halted);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+        assert_eq!(output, HALT);
+    }
+
+    #[test]
+    fn halts_when_input_runs_out() {
+        let source = ".ORIG x3000\nLOOP GETC\nOUT\nBRnzp LOOP\n.END";
+        let (vm, output) = run(source, b"ab");
+        assert!(vm.halted);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3001);
+        assert_eq!(output, b"ab");
+
+        let (_, output) = run(".ORIG x3000\nIN\nHALT\n.END", b"");
+        assert_eq!(output, b"\nInput a character>");
+    }
+}
@@ -0,0 +1,703 @@
+// Interactive debugger driving a `VirtualMachine`.
+//
+// Commands are read from the VM console so they share standard input with
+// the program being debugged. Call depth is tracked by watching JSR, JSRR and
+// RET (JMP R7) as they execute, `next` uses it to step over subroutine calls
+// and `finish` to run until the current subroutine returns. With the OS image
+// loaded TRAP counts as a call too since trap routines return with RET.
+// Taking an interrupt or exception counts as a call returning with RTI.
+//
+// The last steps are recorded so they can be undone with `reverse-step` and
+// `reverse-continue`, program input and output aren't undone.
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::asm::{parse_number, parse_register};
+use crate::os::OsMode;
+use crate::symbols::SymbolTable;
+use crate::trace::condition_codes;
+use crate::vm::{sr1, OPCode, Register, RunResult, VirtualMachine};
+
+// Number of steps that can be undone.
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "Commands:
+    break [addr] (b) : set a breakpoint, lists breakpoints without argument.
+    delete addr (d) : remove a breakpoint.
+    step [n] (s) : execute n instructions, one by default.
+    next (n) : execute one instruction, stepping over subroutine calls.
+    continue (c) : run until a breakpoint is reached or the machine halts.
+    finish : run until the current subroutine returns.
+    reverse-step (rs) : undo the last instruction.
+    reverse-continue (rc) : undo instructions until a breakpoint is reached.
+    regs (r) : show the registers.
+    mem addr [count] (x) : dump memory.
+    list [addr] [count] (l) : disassemble memory, from PC by default.
+    set addr|reg value : write a value to memory or a register.
+    save file : write a snapshot of the machine to a file.
+    load file : restore the machine from a snapshot.
+    quit (q) : leave the debugger.
+Addresses and values are numbers (x3000, #12, 12) or labels, an empty line
+repeats the last command.";
+
+// Why execution stopped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    // The requested step, next or finish completed.
+    Done,
+    // A breakpoint was reached at the given address.
+    Breakpoint(u16),
+    // The machine halted.
+    Halted(RunResult),
+    // Stepping back reached the oldest recorded step.
+    HistoryStart,
+}
+
+// Change in call depth caused by executing the instruction at PC. Handlers
+// entered by an interrupt or exception return with RTI.
+fn depth_change(vm: &VirtualMachine) -> i32 {
+    let inst = vm.read(vm.registers[Register::Pc as usize] as usize);
+    match OPCode::get(inst >> 12) {
+        Some(OPCode::Jsr) => 1,
+        Some(OPCode::Trap) if vm.os == OsMode::Image => 1,
+        Some(OPCode::Jmp) if sr1(inst) == 7 => -1,
+        // In user mode RTI raises a privilege violation instead.
+        Some(OPCode::Rti) if vm.user_mode() => 1,
+        Some(OPCode::Rti) => -1,
+        Some(OPCode::Res) => 1,
+        _ => 0,
+    }
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    pub vm: VirtualMachine,
+    pub symbols: SymbolTable,
+    breakpoints: BTreeSet<u16>,
+    // Command repeated on an empty line.
+    last: String,
+}
+
+impl Debugger {
+    pub fn new(mut vm: VirtualMachine, symbols: SymbolTable) -> Self {
+        vm.enable_history(HISTORY);
+        Self {
+            vm,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            last: String::new(),
+        }
+    }
+
+    // Set a breakpoint, returns false if there already was one.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    // Remove a breakpoint, returns false if there was none.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    // Breakpoint addresses in increasing order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Resolve an address or value written as a label or a number.
+    pub fn value(&self, token: &str) -> Option<u16> {
+        self.symbols.get(token).or_else(|| {
+            parse_number(token)
+                .filter(|n| (-0x8000..=0xFFFF).contains(n))
+                .map(|n| n as u16)
+        })
+    }
+
+    fn pc(&self) -> u16 {
+        self.vm.registers[Register::Pc as usize]
+    }
+
+    fn halted(&self) -> Stop {
+        Stop::Halted(self.vm.result())
+    }
+
+    // Step until `done` returns true for the call depth relative to the
+    // starting point, a breakpoint is reached or the machine halts. At least
+    // one instruction is executed so running from a breakpoint moves on.
+    fn run_until(&mut self, done: impl Fn(i32) -> bool) -> Stop {
+        let mut depth = 0;
+        loop {
+            if self.vm.halted {
+                return self.halted();
+            }
+            self.vm.step_with(|vm, interrupted| {
+                depth += interrupted as i32 + depth_change(vm)
+            });
+            if self.vm.halted {
+                return self.halted();
+            }
+            if self.breakpoints.contains(&self.pc()) {
+                return Stop::Breakpoint(self.pc());
+            }
+            if done(depth) {
+                return Stop::Done;
+            }
+        }
+    }
+
+    // Execute a single instruction.
+    pub fn step(&mut self) -> Stop {
+        self.run_until(|_| true)
+    }
+
+    // Execute a single instruction, running called subroutines to their end.
+    pub fn step_over(&mut self) -> Stop {
+        self.run_until(|depth| depth <= 0)
+    }
+
+    // Run until the current subroutine returns.
+    pub fn step_out(&mut self) -> Stop {
+        self.run_until(|depth| depth < 0)
+    }
+
+    // Run until a breakpoint is reached or the machine halts.
+    pub fn resume(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    // Undo the last step.
+    pub fn reverse_step(&mut self) -> Stop {
+        if self.vm.step_back() {
+            Stop::Done
+        } else {
+            Stop::HistoryStart
+        }
+    }
+
+    // Undo steps until a breakpoint is reached or the history runs out.
+    pub fn reverse_continue(&mut self) -> Stop {
+        loop {
+            if !self.vm.step_back() {
+                return Stop::HistoryStart;
+            }
+            if self.breakpoints.contains(&self.pc()) {
+                return Stop::Breakpoint(self.pc());
+            }
+        }
+    }
+
+    // Disassemble `count` words starting at `address`.
+    fn list(&self, address: u16, count: usize) -> Vec<String> {
+        self.vm
+            .disassemble_with_symbols(address, count, Some(&self.symbols))
+    }
+
+    // Report why execution stopped and show the next instruction.
+    fn report(&mut self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        self.vm.devices.console.flush();
+        match stop {
+            Stop::Done => (),
+            Stop::Breakpoint(address) => {
+                writeln!(out, "Breakpoint x{:04X}", address)?
+            }
+            Stop::HistoryStart => {
+                writeln!(out, "Reached the oldest recorded step")?
+            }
+            Stop::Halted(RunResult::Halted) => {
+                return writeln!(out, "Program halted");
+            }
+            Stop::Halted(RunResult::Unhandled(exception)) => {
+                return writeln!(
+                    out,
+                    "Unhandled exception {:?} before x{:04X}",
+                    exception,
+                    self.pc()
+                );
+            }
+        }
+        writeln!(out, "{}", self.list(self.pc(), 1)[0])
+    }
+
+    fn registers(&self, out: &mut impl Write) -> io::Result<()> {
+        let registers = &self.vm.registers;
+        for row in 0..2 {
+            let line: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|r| format!("R{} x{:04X}", r, registers[r]))
+                .collect();
+            writeln!(out, "{}", line.join("  "))?;
+        }
+        let cc = condition_codes(registers[Register::Cond as usize]);
+        writeln!(
+            out,
+            "PC x{:04X}  PSR x{:04X}  CC {}",
+            self.pc(),
+            self.vm.psr(),
+            cc
+        )
+    }
+
+    // Dump memory eight words per line, device registers are shown as stored
+    // without reading them.
+    fn dump(
+        &self,
+        address: u16,
+        count: usize,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        for row in (0..count).step_by(8) {
+            let start = address.wrapping_add(row as u16);
+            let words: Vec<String> = (row..count.min(row + 8))
+                .map(|i| {
+                    let address = address.wrapping_add(i as u16);
+                    format!("x{:04X}", self.vm.read(address as usize))
+                })
+                .collect();
+            writeln!(out, "x{:04X}  {}", start, words.join(" "))?;
+        }
+        Ok(())
+    }
+
+    // Execute a debugger command, returns false once the user quits.
+    pub fn execute(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+    ) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        let values: Option<Vec<u16>> =
+            args.iter().map(|arg| self.value(arg)).collect();
+        // Optional numeric argument, `default` when absent.
+        let arg = |i: usize, default: u16| {
+            values
+                .as_ref()
+                .map(|values| values.get(i).copied().unwrap_or(default))
+        };
+
+        match command {
+            "break" | "b" if args.is_empty() => {
+                for address in self.breakpoints() {
+                    writeln!(out, "{}", self.list(address, 1)[0])?;
+                }
+            }
+            "break" | "b" => match arg(0, 0) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                    writeln!(out, "Breakpoint at x{:04X}", address)?;
+                }
+                None => writeln!(out, "Unknown address {}", args[0])?,
+            },
+            "delete" | "d" => match arg(0, self.pc()) {
+                Some(address) if self.remove_breakpoint(address) => (),
+                _ => writeln!(out, "No breakpoint at {}", args.join(" "))?,
+            },
+            "step" | "s" => match arg(0, 1) {
+                Some(count) => {
+                    let mut stop = Stop::Done;
+                    for _ in 0..count.max(1) {
+                        stop = self.step();
+                        if stop != Stop::Done {
+                            break;
+                        }
+                    }
+                    self.report(stop, out)?;
+                }
+                None => writeln!(out, "Invalid count {}", args[0])?,
+            },
+            "next" | "n" => {
+                let stop = self.step_over();
+                self.report(stop, out)?;
+            }
+            "continue" | "c" => {
+                let stop = self.resume();
+                self.report(stop, out)?;
+            }
+            "finish" => {
+                let stop = self.step_out();
+                self.report(stop, out)?;
+            }
+            "reverse-step" | "rs" => {
+                let stop = self.reverse_step();
+                self.report(stop, out)?;
+            }
+            "reverse-continue" | "rc" => {
+                let stop = self.reverse_continue();
+                self.report(stop, out)?;
+            }
+            "regs" | "r" => self.registers(out)?,
+            "mem" | "x" if !args.is_empty() => match arg(1, 8) {
+                Some(count) => {
+                    self.dump(arg(0, 0).unwrap_or(0), count as usize, out)?
+                }
+                None => writeln!(out, "Invalid arguments {}", args.join(" "))?,
+            },
+            "list" | "l" => match (arg(0, self.pc()), arg(1, 8)) {
+                (Some(address), Some(count)) => {
+                    for line in self.list(address, count as usize) {
+                        writeln!(out, "{}", line)?;
+                    }
+                }
+                _ => writeln!(out, "Invalid arguments {}", args.join(" "))?,
+            },
+            "set" if args.len() == 2 => {
+                let Some(value) = self.value(args[1]) else {
+                    writeln!(out, "Invalid value {}", args[1])?;
+                    return Ok(true);
+                };
+                if let Some(r) = parse_register(args[0]) {
+                    self.vm.registers[r as usize] = value;
+                } else if args[0].eq_ignore_ascii_case("pc") {
+                    self.vm.registers[Register::Pc as usize] = value;
+                } else if let Some(address) = self.value(args[0]) {
+                    self.vm.poke(address as usize, value);
+                } else {
+                    writeln!(out, "Unknown address {}", args[0])?;
+                }
+            }
+            "save" if args.len() == 1 => {
+                if let Err(err) = fs::write(args[0], self.vm.snapshot()) {
+                    writeln!(out, "{} : {}", args[0], err)?;
+                }
+            }
+            "load" if args.len() == 1 => {
+                let restored = fs::read(args[0])
+                    .map_err(|err| err.to_string())
+                    .and_then(|bytes| {
+                        self.vm.restore(&bytes).map_err(|err| err.to_string())
+                    });
+                match restored {
+                    Ok(()) => self.report(Stop::Done, out)?,
+                    Err(err) => writeln!(out, "{} : {}", args[0], err)?,
+                }
+            }
+            "quit" | "q" => return Ok(false),
+            "help" | "h" => writeln!(out, "{}", HELP)?,
+            _ => {
+                writeln!(out, "Unknown command {}, try help", line)?;
+                return Ok(true);
+            }
+        }
+        self.last = line;
+        Ok(true)
+    }
+
+    // Read a line of input from the VM console, `None` once input ends.
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = vec![];
+        loop {
+            match self.vm.devices.console.read() {
+                Some(b'\n') => break,
+                Some(c) => line.push(c),
+                None if line.is_empty() => return None,
+                None => break,
+            }
+        }
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    // Read and execute commands until the user quits or input ends.
+    pub fn repl(&mut self) -> io::Result<()> {
+        let mut out = io::stdout();
+        self.report(Stop::Done, &mut out)?;
+        loop {
+            write!(out, "(lc-3) ")?;
+            out.flush()?;
+            let Some(line) = self.read_line() else {
+                return writeln!(out);
+            };
+            if !self.execute(&line, &mut out)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+
+    const PROGRAM: &str = "        .ORIG x3000
+MAIN    AND R1, R1, #0
+        ADD R1, R1, #3
+        JSR DOUBLE
+        ADD R2, R1, #0
+        LD R0, CHAR
+        OUT
+        HALT
+DOUBLE  ST R7, SAVE
+        ADD R1, R1, R1
+        JSR INC
+        LD R7, SAVE
+        RET
+INC     ADD R1, R1, #1
+        RET
+CHAR    .FILL x41
+SAVE    .BLKW 1
+        .END";
+
+    // Debugger on `source` loaded at its origin, with the OS image when
+    // `image` is set. Returns the console to check the program output.
+    fn load(
+        source: &str,
+        image: bool,
+        input: &[u8],
+    ) -> (Debugger, ScriptedConsole) {
+        let program = assemble(source).unwrap();
+        let console = ScriptedConsole::new(input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        if image {
+            vm.load_os();
+        }
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = program.origin;
+        (Debugger::new(vm, program.symbols), console)
+    }
+
+    // Execute commands, returns what they printed.
+    fn run(debugger: &mut Debugger, commands: &[&str]) -> String {
+        let mut out = vec![];
+        for command in commands {
+            assert!(debugger.execute(command, &mut out).unwrap());
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn register(debugger: &Debugger, r: Register) -> u16 {
+        debugger.vm.registers[r as usize]
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        assert_eq!(
+            run(&mut debugger, &["b DOUBLE", "break x300C", "b INC", "b"]),
+            "Breakpoint at x3007\n\
+             Breakpoint at x300C\n\
+             Breakpoint at x300C\n\
+             x3007  x3E07  DOUBLE          ST R7, SAVE\n\
+             x300C  x1261  INC             ADD R1, R1, #1\n"
+        );
+        assert_eq!(
+            run(&mut debugger, &["c"]),
+            "Breakpoint x3007\n\
+             x3007  x3E07  DOUBLE          ST R7, SAVE\n"
+        );
+        assert_eq!(
+            run(&mut debugger, &["continue"]),
+            "Breakpoint x300C\n\
+             x300C  x1261  INC             ADD R1, R1, #1\n"
+        );
+        assert_eq!(register(&debugger, Register::R1), 6);
+        assert_eq!(
+            run(&mut debugger, &["d DOUBLE", "delete x3007", "d"]),
+            "No breakpoint at x3007\n"
+        );
+        assert_eq!(debugger.breakpoints().count(), 0);
+        assert_eq!(run(&mut debugger, &["c"]), "Program halted\n");
+        assert_eq!(
+            run(&mut debugger, &["b nowhere", "d nowhere"]),
+            "Unknown address nowhere\nNo breakpoint at nowhere\n"
+        );
+    }
+
+    #[test]
+    fn next_steps_over_jsr() {
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        assert_eq!(
+            run(&mut debugger, &["s 2"]),
+            "x3002  x4804                  JSR DOUBLE\n"
+        );
+        assert_eq!(
+            run(&mut debugger, &["n"]),
+            "x3003  x1460                  ADD R2, R1, #0\n"
+        );
+        assert_eq!(register(&debugger, Register::R1), 7);
+        // Breakpoints in the subroutine still stop `next`.
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        run(&mut debugger, &["s 2", "b INC"]);
+        assert_eq!(
+            run(&mut debugger, &["next"]),
+            "Breakpoint x300C\n\
+             x300C  x1261  INC             ADD R1, R1, #1\n"
+        );
+    }
+
+    #[test]
+    fn next_steps_over_trap() {
+        let (mut debugger, console) = load(PROGRAM, true, b"");
+        run(&mut debugger, &["b x3005", "c"]);
+        assert_eq!(
+            run(&mut debugger, &["n"]),
+            "x3006  xF025                  HALT\n"
+        );
+        assert_eq!(console.output(), b"A");
+        assert_eq!(run(&mut debugger, &["n"]), "Program halted\n");
+        assert!(debugger.vm.halted);
+        // Stepping into the trap routine leaves the program.
+        let (mut debugger, _) = load(PROGRAM, true, b"");
+        run(&mut debugger, &["b x3005", "c", "s"]);
+        assert!(debugger.pc() < 0x3000);
+    }
+
+    #[test]
+    fn finish_returns_from_subroutine() {
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        run(&mut debugger, &["b INC", "c", "d INC"]);
+        assert_eq!(
+            run(&mut debugger, &["finish"]),
+            "x300A  x2E04                  LD R7, SAVE\n"
+        );
+        assert_eq!(register(&debugger, Register::R1), 7);
+        assert_eq!(
+            run(&mut debugger, &["finish"]),
+            "x3003  x1460                  ADD R2, R1, #0\n"
+        );
+        // An empty line repeats the last command.
+        assert_eq!(run(&mut debugger, &[""]), "Program halted\n");
+    }
+
+    #[test]
+    fn memory_and_registers() {
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        assert_eq!(
+            run(&mut debugger, &["x MAIN 3", "mem x300B 10"]),
+            "x3000  x5260 x1263 x4804\n\
+             x300B  xC1C0 x1261 xC1C0 x0041 x0000 x0000 x0000 x0000\n\
+             x3013  x0000 x0000\n"
+        );
+        assert_eq!(
+            run(&mut debugger, &["l DOUBLE 2", "list"]),
+            "x3007  x3E07  DOUBLE          ST R7, SAVE\n\
+             x3008  x1241                  ADD R1, R1, R1\n\
+             x3000  x5260  MAIN            AND R1, R1, #0\n\
+             x3001  x1263                  ADD R1, R1, #3\n\
+             x3002  x4804                  JSR DOUBLE\n\
+             x3003  x1460                  ADD R2, R1, #0\n\
+             x3004  x2009                  LD R0, CHAR\n\
+             x3005  xF021                  OUT\n\
+             x3006  xF025                  HALT\n\
+             x3007  x3E07  DOUBLE          ST R7, SAVE\n"
+        );
+        assert_eq!(
+            run(
+                &mut debugger,
+                &["set R3 x-1", "set pc DOUBLE", "set CHAR #66", "regs"]
+            ),
+            "R0 x0000  R1 x0000  R2 x0000  R3 xFFFF\n\
+             R4 x0000  R5 x0000  R6 x0000  R7 x0000\n\
+             PC x3007  PSR x8000  CC \n"
+        );
+        assert_eq!(debugger.vm.memory[0x300E], 66);
+        assert_eq!(
+            run(&mut debugger, &["set R3 foo", "set R9 1", "x"]),
+            "Invalid value foo\nUnknown address R9\nUnknown command x, try help\n"
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir()
+            .join(format!("lc3-debug-{}.snap", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        run(&mut debugger, &["b INC", "c"]);
+        assert_eq!(run(&mut debugger, &[&format!("save {}", path)]), "");
+        run(&mut debugger, &["d INC", "c"]);
+        assert!(debugger.vm.halted);
+        assert_eq!(
+            run(&mut debugger, &[&format!("load {}", path)]),
+            "x300C  x1261  INC             ADD R1, R1, #1\n"
+        );
+        assert!(!debugger.vm.halted);
+        assert_eq!(register(&debugger, Register::R1), 6);
+        // Steps before the snapshot can't be undone.
+        assert_eq!(debugger.reverse_step(), Stop::HistoryStart);
+        assert_eq!(run(&mut debugger, &["c"]), "Program halted\n");
+        assert_eq!(register(&debugger, Register::R2), 7);
+        std::fs::remove_file(path).unwrap();
+
+        let missing = format!("{}.missing", path);
+        let out = run(&mut debugger, &[&format!("load {}", missing)]);
+        assert!(out.starts_with(&format!("{} : ", missing)), "{}", out);
+    }
+
+    #[test]
+    fn next_runs_interrupt_handlers() {
+        // The keyboard interrupt is taken as `next` steps over the ADD.
+        let (mut debugger, _) = load(
+            "        .ORIG x3000
+        LD R0, ENABLE
+        STI R0, KBSR
+LOOP    ADD R1, R1, #1
+        BR LOOP
+ENABLE  .FILL x4000
+KBSR    .FILL xFE00
+        .END",
+            false,
+            b"",
+        );
+        let handler = assemble(
+            ".ORIG x1000\nLDI R2, DATA\nJSR SUB\nRTI\nSUB RET\n\
+             DATA .FILL xFE02\n.END",
+        )
+        .unwrap();
+        let vm = &mut debugger.vm;
+        vm.load_image("handler", &handler.to_obj()).unwrap();
+        vm.memory[0x0180] = 0x1000;
+        vm.registers[Register::R6 as usize] = 0xFE00;
+        run(&mut debugger, &["s 2"]);
+        debugger.vm.devices.console = Box::new(ScriptedConsole::new(b"k"));
+        assert_eq!(
+            run(&mut debugger, &["n"]),
+            "x3002  x1261  LOOP            ADD R1, R1, #1\n"
+        );
+        // The handler ran, the ADD it interrupted didn't yet.
+        assert_eq!(register(&debugger, Register::R2), b'k' as u16);
+        assert_eq!(register(&debugger, Register::R1), 0);
+        assert!(debugger.vm.user_mode());
+        assert_eq!(
+            run(&mut debugger, &["n", "n"]),
+            "x3003  x0FFE                  BRnzp LOOP\n\
+             x3002  x1261  LOOP            ADD R1, R1, #1\n"
+        );
+        assert_eq!(register(&debugger, Register::R1), 1);
+    }
+
+    #[test]
+    fn reverse_step_keeps_edits() {
+        let (mut debugger, _) = load(
+            ".ORIG x3000\nADD R0, R0, #1\nST R0, DATA\nHALT\n\
+             DATA .FILL #0\n.END",
+            false,
+            b"",
+        );
+        // Edits made between steps belong to neither of them.
+        run(
+            &mut debugger,
+            &["step", "set DATA #9", "set x4000 x1234", "step"],
+        );
+        assert_eq!(debugger.vm.memory[0x3003], 1);
+        run(&mut debugger, &["reverse-step"]);
+        assert_eq!(debugger.vm.memory[0x3003], 9);
+        run(&mut debugger, &["rs"]);
+        assert_eq!(debugger.pc(), 0x3000);
+        assert_eq!(debugger.vm.memory[0x3003], 9);
+        assert_eq!(debugger.vm.memory[0x4000], 0x1234);
+        assert_eq!(debugger.reverse_step(), Stop::HistoryStart);
+    }
+
+    #[test]
+    fn quit() {
+        let (mut debugger, _) = load(PROGRAM, false, b"");
+        assert!(!debugger.execute("q", &mut vec![]).unwrap());
+    }
+}
@@ -0,0 +1,264 @@
+// Decoded instructions.
+//
+// The interpreter decodes each word once into an `Instruction` holding its
+// operands, and keeps it in a `DecodeCache` indexed by address. Immediates
+// are sign extended and, since an entry belongs to a single address, PC
+// relative offsets are resolved to the addresses they point to. Writing to an
+// address drops its entry so self-modifying code is decoded again before it
+// runs.
+use crate::vm::{
+    dr, imm5, imm_mode, jsr_long, nzp, offset6, pc_offset11, pc_offset9, sr1,
+    sr2, trapvect8, OPCode, MEMORY_MAX,
+};
+
+// An instruction with its fields extracted, registers are numbered 0 to 7.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Add { dr: u8, sr1: u8, sr2: u8 },
+    AddImm { dr: u8, sr1: u8, imm: u16 },
+    And { dr: u8, sr1: u8, sr2: u8 },
+    AndImm { dr: u8, sr1: u8, imm: u16 },
+    Not { dr: u8, sr: u8 },
+    Br { nzp: u16, target: u16 },
+    Jmp { base: u8 },
+    Jsr { target: u16 },
+    Jsrr { base: u8 },
+    Ld { dr: u8, address: u16 },
+    // `pointer` holds the address of the word to load.
+    Ldi { dr: u8, pointer: u16 },
+    Ldr { dr: u8, base: u8, offset: u16 },
+    Lea { dr: u8, address: u16 },
+    St { sr: u8, address: u16 },
+    Sti { sr: u8, pointer: u16 },
+    Str { sr: u8, base: u8, offset: u16 },
+    Trap { vector: u8 },
+    Rti,
+    // The reserved opcode 1101.
+    Reserved,
+}
+
+// Decode the instruction word found at an address.
+pub fn decode(address: u16, inst: u16) -> Instruction {
+    let op = OPCode::get(inst >> 12).expect("opcode is four bits wide");
+    let dr = dr(inst) as u8;
+    let sr1 = sr1(inst) as u8;
+    let sr2 = sr2(inst) as u8;
+    let imm = imm5(inst);
+    // PC relative offsets are from the incremented PC.
+    let relative = |offset: u16| address.wrapping_add(1).wrapping_add(offset);
+    match op {
+        OPCode::Add if imm_mode(inst) => Instruction::AddImm { dr, sr1, imm },
+        OPCode::Add => Instruction::Add { dr, sr1, sr2 },
+        OPCode::And if imm_mode(inst) => Instruction::AndImm { dr, sr1, imm },
+        OPCode::And => Instruction::And { dr, sr1, sr2 },
+        OPCode::Not => Instruction::Not { dr, sr: sr1 },
+        OPCode::Br => Instruction::Br {
+            nzp: nzp(inst),
+            target: relative(pc_offset9(inst)),
+        },
+        OPCode::Jmp => Instruction::Jmp { base: sr1 },
+        OPCode::Jsr if jsr_long(inst) => Instruction::Jsr {
+            target: relative(pc_offset11(inst)),
+        },
+        OPCode::Jsr => Instruction::Jsrr { base: sr1 },
+        OPCode::Ld => Instruction::Ld {
+            dr,
+            address: relative(pc_offset9(inst)),
+        },
+        OPCode::Ldi => Instruction::Ldi {
+            dr,
+            pointer: relative(pc_offset9(inst)),
+        },
+        OPCode::Ldr => Instruction::Ldr {
+            dr,
+            base: sr1,
+            offset: offset6(inst),
+        },
+        OPCode::Lea => Instruction::Lea {
+            dr,
+            address: relative(pc_offset9(inst)),
+        },
+        OPCode::St => Instruction::St {
+            sr: dr,
+            address: relative(pc_offset9(inst)),
+        },
+        OPCode::Sti => Instruction::Sti {
+            sr: dr,
+            pointer: relative(pc_offset9(inst)),
+        },
+        OPCode::Str => Instruction::Str {
+            sr: dr,
+            base: sr1,
+            offset: offset6(inst),
+        },
+        OPCode::Trap => Instruction::Trap {
+            vector: trapvect8(inst) as u8,
+        },
+        OPCode::Rti => Instruction::Rti,
+        OPCode::Res => Instruction::Reserved,
+    }
+}
+
+// Decoded instructions by address.
+#[derive(Clone, Debug)]
+pub struct DecodeCache {
+    // Boxed array so indexing by address needs no bounds check.
+    entries: Box<[Option<Instruction>; MEMORY_MAX]>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![None; MEMORY_MAX]
+                .into_boxed_slice()
+                .try_into()
+                .expect("cache has an entry per address"),
+        }
+    }
+
+    // Instruction at an address, decoded from memory the first time.
+    pub fn get(
+        &mut self,
+        address: u16,
+        memory: &[u16; MEMORY_MAX],
+    ) -> Instruction {
+        let word = memory[address as usize];
+        *self.entries[address as usize]
+            .get_or_insert_with(|| decode(address, word))
+    }
+
+    // Drop the instruction decoded from an address after it was written.
+    pub fn invalidate(&mut self, address: u16) {
+        self.entries[address as usize] = None;
+    }
+
+    // Drop all decoded instructions.
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::ScriptedConsole;
+    use crate::vm::{Register, VirtualMachine};
+
+    // ADD R0, R0, #1 and ADD R0, R0, #2.
+    const ADD_1: u16 = 0x1021;
+    const ADD_2: u16 = 0x1022;
+
+    fn machine() -> VirtualMachine {
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")));
+        vm.memory[0x3000] = ADD_1;
+        vm
+    }
+
+    // Execute the instruction at `address`, returns what it added to R0.
+    fn added(vm: &mut VirtualMachine, address: u16) -> u16 {
+        let before = vm.registers[Register::R0 as usize];
+        vm.registers[Register::Pc as usize] = address;
+        vm.step();
+        vm.registers[Register::R0 as usize].wrapping_sub(before)
+    }
+
+    #[test]
+    fn decodes_once() {
+        let mut cache = DecodeCache::new();
+        let mut memory = [0; MEMORY_MAX];
+        memory[0x3000] = 0x0FFE;
+        let br = Instruction::Br {
+            nzp: 7,
+            target: 0x2FFF,
+        };
+        assert_eq!(cache.get(0x3000, &memory), br);
+        memory[0x3000] = ADD_1;
+        assert_eq!(cache.get(0x3000, &memory), br);
+        cache.invalidate(0x3000);
+        let add = Instruction::AddImm {
+            dr: 0,
+            sr1: 0,
+            imm: 1,
+        };
+        assert_eq!(cache.get(0x3000, &memory), add);
+        memory[0x3000] = ADD_2;
+        cache.clear();
+        assert_eq!(
+            cache.get(0x3000, &memory),
+            Instruction::AddImm {
+                dr: 0,
+                sr1: 0,
+                imm: 2
+            }
+        );
+    }
+
+    #[test]
+    fn invalidated_by_poke() {
+        let mut vm = machine();
+        assert_eq!(added(&mut vm, 0x3000), 1);
+        vm.poke(0x3000, ADD_2);
+        assert_eq!(added(&mut vm, 0x3000), 2);
+    }
+
+    #[test]
+    fn invalidated_by_mem_write() {
+        let mut vm = machine();
+        assert_eq!(added(&mut vm, 0x3000), 1);
+        vm.mem_write(0x3000, ADD_2);
+        assert_eq!(added(&mut vm, 0x3000), 2);
+    }
+
+    #[test]
+    fn invalidated_by_store() {
+        let mut vm = machine();
+        // ST R1, x3000
+        vm.memory[0x3001] = 0x33FE;
+        vm.registers[Register::R1 as usize] = ADD_2;
+        assert_eq!(added(&mut vm, 0x3000), 1);
+        assert_eq!(added(&mut vm, 0x3001), 0);
+        assert_eq!(added(&mut vm, 0x3000), 2);
+    }
+
+    #[test]
+    fn invalidated_by_load_image() {
+        let mut vm = machine();
+        assert_eq!(added(&mut vm, 0x3000), 1);
+        vm.load_image("patch", &[0x30, 0x00, 0x10, 0x22]).unwrap();
+        assert_eq!(added(&mut vm, 0x3000), 2);
+    }
+
+    #[test]
+    fn invalidated_by_step_back() {
+        let mut vm = machine();
+        vm.enable_history(10);
+        // ST R1, x3000
+        vm.memory[0x3001] = 0x33FE;
+        vm.registers[Register::R1 as usize] = ADD_2;
+        assert_eq!(added(&mut vm, 0x3000), 1);
+        assert_eq!(added(&mut vm, 0x3001), 0);
+        assert_eq!(added(&mut vm, 0x3000), 2);
+        // Undo the patched ADD, then the store patching it.
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert_eq!(vm.memory[0x3000], ADD_1);
+        assert_eq!(added(&mut vm, 0x3000), 1);
+    }
+
+    #[test]
+    fn cleared_by_restore() {
+        let mut vm = machine();
+        let snapshot = vm.snapshot();
+        vm.mem_write(0x3000, ADD_2);
+        assert_eq!(added(&mut vm, 0x3000), 2);
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(added(&mut vm, 0x3000), 1);
+    }
+}
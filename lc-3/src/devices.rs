@@ -0,0 +1,296 @@
+// Memory mapped device registers.
+//
+// The keyboard and display registers are backed by a console, the machine
+// control register stops the clock when its top bit is cleared. Polling the
+// keyboard once console input has ended clears it too.
+use crate::console::Console;
+
+// Keyboard status register, bit 15 is set when a key is ready.
+pub const KBSR: u16 = 0xfe00;
+// Keyboard data register, holds the last key typed.
+pub const KBDR: u16 = 0xfe02;
+// Display status register, bit 15 is set when the display is ready.
+pub const DSR: u16 = 0xfe04;
+// Display data register, characters written here are displayed.
+pub const DDR: u16 = 0xfe06;
+// Machine control register, clearing bit 15 halts the machine.
+pub const MCR: u16 = 0xfffe;
+
+// Ready bit of the status registers.
+const READY: u16 = 1 << 15;
+// Interrupt enable bit of the status registers.
+const INTERRUPT_ENABLE: u16 = 1 << 14;
+
+// Keyboard interrupt vector and priority level.
+pub const KEYBOARD_VECTOR: u8 = 0x80;
+pub const KEYBOARD_PRIORITY: u16 = 4;
+
+// Device registers that aren't backed by the console, saved in snapshots.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceState {
+    pub kbdr: u16,
+    pub key_ready: bool,
+    pub key_interrupts: bool,
+    pub mcr: u16,
+}
+
+#[derive(Debug)]
+pub struct Devices {
+    pub console: Box<dyn Console>,
+    // Last key typed and whether it was read from KBDR yet.
+    kbdr: u16,
+    key_ready: bool,
+    // Whether the keyboard raises an interrupt when a key is ready.
+    key_interrupts: bool,
+    mcr: u16,
+}
+
+impl Devices {
+    pub fn new(console: Box<dyn Console>) -> Self {
+        Self {
+            console,
+            kbdr: 0,
+            key_ready: false,
+            key_interrupts: false,
+            mcr: READY,
+        }
+    }
+
+    pub fn state(&self) -> DeviceState {
+        DeviceState {
+            kbdr: self.kbdr,
+            key_ready: self.key_ready,
+            key_interrupts: self.key_interrupts,
+            mcr: self.mcr,
+        }
+    }
+
+    // Replace the device registers, the console is kept.
+    pub fn set_state(&mut self, state: DeviceState) {
+        self.kbdr = state.kbdr;
+        self.key_ready = state.key_ready;
+        self.key_interrupts = state.key_interrupts;
+        self.mcr = state.mcr;
+    }
+
+    // Whether an address maps to a device register instead of memory.
+    pub fn maps(address: u16) -> bool {
+        matches!(address, KBSR | KBDR | DSR | DDR | MCR)
+    }
+
+    // Whether the clock is enabled in the machine control register.
+    pub fn running(&self) -> bool {
+        self.mcr & READY != 0
+    }
+
+    // Read a device register, checking for a key doesn't block.
+    pub fn read(&mut self, address: u16) -> u16 {
+        match address {
+            KBSR => {
+                let mut kbsr = 0;
+                if self.poll_key() {
+                    kbsr |= READY;
+                } else if self.console.closed() {
+                    // Nothing will ever be typed, stop the clock instead of
+                    // letting the program poll forever.
+                    self.mcr &= !READY;
+                }
+                if self.key_interrupts {
+                    kbsr |= INTERRUPT_ENABLE;
+                }
+                kbsr
+            }
+            KBDR => {
+                self.key_ready = false;
+                self.kbdr
+            }
+            DSR => READY,
+            DDR => 0,
+            MCR => self.mcr,
+            _ => unreachable!("x{:04X} isn't a device register", address),
+        }
+    }
+
+    // Write a device register, writes to read only registers are ignored.
+    pub fn write(&mut self, address: u16, value: u16) {
+        match address {
+            DDR => {
+                self.console.write(value as u8);
+                self.console.flush();
+            }
+            MCR => self.mcr = value,
+            KBSR => self.key_interrupts = value & INTERRUPT_ENABLE != 0,
+            KBDR | DSR => (),
+            _ => unreachable!("x{:04X} isn't a device register", address),
+        }
+    }
+
+    // Latch a key in KBDR if one was typed, returns whether a key is ready.
+    fn poll_key(&mut self) -> bool {
+        if !self.key_ready {
+            if let Some(key) = self.console.poll() {
+                self.kbdr = key as u16;
+                self.key_ready = true;
+            }
+        }
+        self.key_ready
+    }
+
+    // Interrupt vector and priority of a pending device interrupt.
+    pub fn pending_interrupt(&mut self) -> Option<(u8, u16)> {
+        if self.key_interrupts && self.poll_key() {
+            Some((KEYBOARD_VECTOR, KEYBOARD_PRIORITY))
+        } else {
+            None
+        }
+    }
+
+    // Block until a key is typed, a key already latched in KBDR comes first.
+    pub fn getc(&mut self) -> Option<u8> {
+        if self.key_ready {
+            self.key_ready = false;
+            Some(self.kbdr as u8)
+        } else {
+            self.console.read()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+    use crate::vm::{Register, RunResult, VirtualMachine};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    // Console typing a key or nothing on each poll, blocking reads fail the
+    // test.
+    #[derive(Debug)]
+    struct Keys {
+        polls: VecDeque<Option<u8>>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Console for Keys {
+        fn read(&mut self) -> Option<u8> {
+            panic!("blocking read");
+        }
+
+        fn poll(&mut self) -> Option<u8> {
+            self.polls.pop_front().flatten()
+        }
+
+        fn write(&mut self, c: u8) {
+            self.output.borrow_mut().push(c)
+        }
+    }
+
+    fn devices(polls: &[Option<u8>]) -> (Devices, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::<RefCell<Vec<u8>>>::default();
+        let console = Keys {
+            polls: polls.iter().copied().collect(),
+            output: output.clone(),
+        };
+        (Devices::new(Box::new(console)), output)
+    }
+
+    #[test]
+    fn kbsr_polls_without_blocking() {
+        let (mut devices, _) = devices(&[None, None, Some(b'a')]);
+        assert_eq!(devices.read(KBSR), 0);
+        assert_eq!(devices.read(KBSR), 0);
+        assert_eq!(devices.read(KBSR), READY);
+        // The key stays latched until KBDR is read, without polling again.
+        assert_eq!(devices.read(KBSR), READY);
+        assert!(devices.running());
+    }
+
+    #[test]
+    fn kbdr_read_clears_kbsr() {
+        let (mut devices, _) = devices(&[Some(b'a'), None, Some(b'b')]);
+        assert_eq!(devices.read(KBSR), READY);
+        assert_eq!(devices.read(KBDR), b'a' as u16);
+        assert_eq!(devices.read(KBSR), 0);
+        // KBDR keeps the last key.
+        assert_eq!(devices.read(KBDR), b'a' as u16);
+        assert_eq!(devices.read(KBSR), READY);
+        assert_eq!(devices.read(KBDR), b'b' as u16);
+    }
+
+    #[test]
+    fn kbsr_interrupt_enable() {
+        let (mut devices, _) = devices(&[None, Some(b'a')]);
+        devices.write(KBSR, INTERRUPT_ENABLE | READY);
+        assert_eq!(devices.read(KBSR), INTERRUPT_ENABLE);
+        assert_eq!(devices.read(KBSR), INTERRUPT_ENABLE | READY);
+        devices.write(KBSR, 0);
+        assert_eq!(devices.read(KBSR), READY);
+    }
+
+    #[test]
+    fn ddr_writes_reach_console() {
+        let (mut devices, output) = devices(&[]);
+        assert_eq!(devices.read(DSR), READY);
+        devices.write(DDR, b'h' as u16);
+        devices.write(DDR, 0x1269);
+        assert_eq!(*output.borrow(), b"hi");
+        assert_eq!(devices.read(DDR), 0);
+    }
+
+    #[test]
+    fn read_only_registers_ignore_writes() {
+        let (mut devices, _) = devices(&[]);
+        devices.write(KBDR, 0x1234);
+        devices.write(DSR, 0);
+        assert_eq!(devices.read(KBDR), 0);
+        assert_eq!(devices.read(DSR), READY);
+    }
+
+    #[test]
+    fn closed_console_stops_clock() {
+        let mut devices = Devices::new(Box::new(ScriptedConsole::new(b"a")));
+        assert_eq!(devices.read(KBSR), READY);
+        assert_eq!(devices.read(KBDR), b'a' as u16);
+        assert!(devices.running());
+        assert_eq!(devices.read(KBSR), 0);
+        assert!(!devices.running());
+    }
+
+    #[test]
+    fn mcr_halts_machine() {
+        let program = assemble(
+            "        .ORIG x3000
+        LD R0, STOP
+        STI R0, MCR_ADDR
+        ADD R1, R1, #1
+        HALT
+STOP    .FILL x7FFF
+MCR_ADDR .FILL xFFFE
+        .END",
+        )
+        .unwrap();
+        let console = ScriptedConsole::new(b"");
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = program.origin;
+        assert_eq!(vm.run(), RunResult::Halted);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3002);
+        assert_eq!(vm.registers[Register::R1 as usize], 0);
+        assert_eq!(vm.devices.read(MCR), 0x7FFF);
+        assert!(console.output().is_empty());
+    }
+
+    #[test]
+    fn mcr_keeps_running_with_bit_15_set() {
+        let (mut devices, _) = devices(&[]);
+        assert_eq!(devices.read(MCR), READY);
+        devices.write(MCR, 0x8001);
+        assert!(devices.running());
+        assert_eq!(devices.read(MCR), 0x8001);
+        devices.write(MCR, 0);
+        assert!(!devices.running());
+    }
+}
@@ -0,0 +1,244 @@
+// Disassembler turning memory words back into LC-3 assembly.
+//
+// PC relative operands are resolved to absolute addresses, or to labels when
+// a symbol table is available.
+use crate::symbols::SymbolTable;
+use crate::vm::{
+    dr, imm5, imm_mode, jsr_long, nzp, offset6, pc_offset11, pc_offset9, sr1,
+    sr2, trapvect8, OPCode, VirtualMachine,
+};
+
+// Render an address as a label if one is defined there.
+fn target(address: u16, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|s| s.name_at(address)) {
+        Some(name) => name.to_string(),
+        None => format!("x{:04X}", address),
+    }
+}
+
+// Disassemble the instruction `inst` stored at `address`.
+pub fn instruction(
+    address: u16,
+    inst: u16,
+    symbols: Option<&SymbolTable>,
+) -> String {
+    // PC relative offsets are computed from the incremented PC.
+    let pc = address.wrapping_add(1);
+    let pc_relative = |offset: u16| target(pc.wrapping_add(offset), symbols);
+    let op = OPCode::get(inst >> 12).expect("opcode is four bits wide");
+
+    match op {
+        OPCode::Add | OPCode::And => {
+            let name = if let OPCode::Add = op { "ADD" } else { "AND" };
+            let src = if imm_mode(inst) {
+                format!("#{}", imm5(inst) as i16)
+            } else {
+                format!("R{}", sr2(inst))
+            };
+            format!("{} R{}, R{}, {}", name, dr(inst), sr1(inst), src)
+        }
+        OPCode::Not => format!("NOT R{}, R{}", dr(inst), sr1(inst)),
+        OPCode::Br => {
+            let cond = nzp(inst);
+            if cond == 0 {
+                return "NOP".to_string();
+            }
+            let mut name = String::from("BR");
+            for (bit, flag) in [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')] {
+                if cond & bit != 0 {
+                    name.push(flag);
+                }
+            }
+            format!("{} {}", name, pc_relative(pc_offset9(inst)))
+        }
+        OPCode::Jmp if sr1(inst) == 7 => "RET".to_string(),
+        OPCode::Jmp => format!("JMP R{}", sr1(inst)),
+        OPCode::Jsr if jsr_long(inst) => {
+            format!("JSR {}", pc_relative(pc_offset11(inst)))
+        }
+        OPCode::Jsr => format!("JSRR R{}", sr1(inst)),
+        OPCode::Ld | OPCode::Ldi | OPCode::Lea | OPCode::St | OPCode::Sti => {
+            let name = match op {
+                OPCode::Ld => "LD",
+                OPCode::Ldi => "LDI",
+                OPCode::Lea => "LEA",
+                OPCode::St => "ST",
+                _ => "STI",
+            };
+            format!("{} R{}, {}", name, dr(inst), pc_relative(pc_offset9(inst)))
+        }
+        OPCode::Ldr | OPCode::Str => {
+            let name = if let OPCode::Ldr = op { "LDR" } else { "STR" };
+            format!(
+                "{} R{}, R{}, #{}",
+                name,
+                dr(inst),
+                sr1(inst),
+                offset6(inst) as i16
+            )
+        }
+        OPCode::Trap => match trapvect8(inst) {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        OPCode::Rti => "RTI".to_string(),
+        OPCode::Res => format!(".FILL x{:04X}", inst),
+    }
+}
+
+// Disassemble a word as a listing line with its address, encoding and label.
+pub fn line(address: u16, inst: u16, symbols: Option<&SymbolTable>) -> String {
+    let label = symbols.and_then(|s| s.name_at(address)).unwrap_or("");
+    format!(
+        "x{:04X}  x{:04X}  {:<16}{}",
+        address,
+        inst,
+        label,
+        instruction(address, inst, symbols)
+    )
+    .trim_end()
+    .to_string()
+}
+
+impl VirtualMachine {
+    // Disassemble `count` words of memory starting at `address`.
+    pub fn disassemble(&self, address: u16, count: usize) -> Vec<String> {
+        self.disassemble_with_symbols(address, count, None)
+    }
+
+    // Disassemble `count` words of memory starting at `address`, using labels
+    // from the symbol table where possible.
+    pub fn disassemble_with_symbols(
+        &self,
+        address: u16,
+        count: usize,
+        symbols: Option<&SymbolTable>,
+    ) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let address = address.wrapping_add(i as u16);
+                line(address, self.memory[address as usize], symbols)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn render(inst: u16) -> String {
+        instruction(0x3000, inst, None)
+    }
+
+    #[test]
+    fn operates() {
+        assert_eq!(render(0x1283), "ADD R1, R2, R3");
+        assert_eq!(render(0x12BF), "ADD R1, R2, #-1");
+        assert_eq!(render(0x1A2F), "ADD R5, R0, #15");
+        assert_eq!(render(0x5283), "AND R1, R2, R3");
+        assert_eq!(render(0x5030), "AND R0, R0, #-16");
+        assert_eq!(render(0x92BF), "NOT R1, R2");
+    }
+
+    #[test]
+    fn control() {
+        assert_eq!(render(0x0E00), "BRnzp x3001");
+        assert_eq!(render(0x0802), "BRn x3003");
+        assert_eq!(render(0x05FF), "BRz x3000");
+        assert_eq!(render(0x0BFE), "BRnp x2FFF");
+        assert_eq!(render(0x0300), "BRp x2F01");
+        assert_eq!(render(0x0000), "NOP");
+        assert_eq!(render(0xC0C0), "JMP R3");
+        assert_eq!(render(0xC1C0), "RET");
+        assert_eq!(render(0x4FF8), "JSR x2FF9");
+        assert_eq!(render(0x4BFF), "JSR x3400");
+        assert_eq!(render(0x4100), "JSRR R4");
+        assert_eq!(render(0x8000), "RTI");
+    }
+
+    #[test]
+    fn memory() {
+        assert_eq!(render(0x200C), "LD R0, x300D");
+        assert_eq!(render(0xA3FF), "LDI R1, x3000");
+        assert_eq!(render(0xE500), "LEA R2, x2F01");
+        assert_eq!(render(0x36FF), "ST R3, x3100");
+        assert_eq!(render(0xB9FE), "STI R4, x2FFF");
+        assert_eq!(render(0x62BE), "LDR R1, R2, #-2");
+        assert_eq!(render(0x6A1F), "LDR R5, R0, #31");
+        assert_eq!(render(0x7760), "STR R3, R5, #-32");
+    }
+
+    #[test]
+    fn traps() {
+        let aliases = ["GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT"];
+        for (vector, alias) in (0x20..).zip(aliases) {
+            assert_eq!(render(0xF000 | vector), alias);
+        }
+        assert_eq!(render(0xF026), "TRAP x26");
+        assert_eq!(render(0xF000), "TRAP x00");
+    }
+
+    #[test]
+    fn reserved_and_data() {
+        // The reserved opcode can't be an instruction, it shows as data.
+        assert_eq!(render(0xD123), ".FILL xD123");
+        // Other data decodes as whatever instruction shares its bits.
+        assert_eq!(render(0x0005), "NOP");
+        assert_eq!(render(0x0048), "NOP");
+        assert_eq!(render(0x0061), "NOP");
+        assert_eq!(render(0xFFFF), "TRAP xFF");
+    }
+
+    #[test]
+    fn wraps_around_memory() {
+        assert_eq!(instruction(0xFFFF, 0x0E00, None), "BRnzp x0000");
+        assert_eq!(instruction(0x0000, 0x0FFE, None), "BRnzp xFFFF");
+    }
+
+    #[test]
+    fn labels() {
+        let program = assemble(
+            "        .ORIG x3000
+LOOP    LD R0, DATA
+        ADD R0, R0, #-1
+        BRp LOOP
+        JSR SUB
+        LEA R1, x10
+        HALT
+SUB     RET
+DATA    .FILL #3
+        .END",
+        )
+        .unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.load_image("labels", &program.to_obj()).unwrap();
+        assert_eq!(
+            vm.disassemble_with_symbols(0x3000, 8, Some(&program.symbols)),
+            [
+                "x3000  x2006  LOOP            LD R0, DATA",
+                "x3001  x103F                  ADD R0, R0, #-1",
+                "x3002  x03FD                  BRp LOOP",
+                "x3003  x4802                  JSR SUB",
+                "x3004  xE210                  LEA R1, x3015",
+                "x3005  xF025                  HALT",
+                "x3006  xC1C0  SUB             RET",
+                "x3007  x0003  DATA            NOP",
+            ]
+        );
+        assert_eq!(
+            vm.disassemble(0x3000, 3),
+            [
+                "x3000  x2006                  LD R0, x3007",
+                "x3001  x103F                  ADD R0, R0, #-1",
+                "x3002  x03FD                  BRp x3000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,519 @@
+// GDB remote serial protocol stub.
+//
+// A single debugger connection is accepted on a TCP socket and served until
+// it detaches, kills the program or the program halts. Execution control and
+// breakpoints are delegated to `Debugger`.
+//
+// Like other word addressed targets GDB sees byte addresses, the word at
+// LC-3 address A covers bytes 2A and 2A + 1 and PC is reported as a byte
+// address. Byte addresses need 17 bits so PC is a 32-bit register. Words and
+// registers are sent big-endian, as in object files. The registers are R0 to
+// R7, PC and PSR, described in `target.xml`.
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debug::{Debugger, Stop};
+use crate::interrupts::Exception;
+use crate::vm::{Register, RunResult};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target>
+  <feature name="org.lc3.core">
+    <reg name="r0" bitsize="16" type="int16"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="int16"/>
+    <reg name="r5" bitsize="16" type="int16"/>
+    <reg name="r6" bitsize="16" type="data_ptr"/>
+    <reg name="r7" bitsize="16" type="code_ptr"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="psr" bitsize="16" type="int16"/>
+  </feature>
+</target>
+"#;
+
+// Number of registers exposed to GDB.
+const REGISTERS: usize = 10;
+// GDB number of the PC register.
+const PC: usize = 8;
+// Instructions executed between checks for an interrupt request.
+const INTERRUPT_CHECK: usize = 4096;
+// Byte GDB sends to interrupt a running program.
+const INTERRUPT: u8 = 0x03;
+
+// How a debugging session ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    // The debugger detached, the program should keep running.
+    Detached,
+    // The debugger killed the program.
+    Killed,
+    // The program halted and the debugger was told so.
+    Exited,
+}
+
+// Size in bytes of a register as described in `target.xml`.
+fn register_size(n: usize) -> usize {
+    if n == PC {
+        4
+    } else {
+        2
+    }
+}
+
+// Register value as sent to GDB.
+fn hex_register(n: usize, value: u32) -> String {
+    format!("{:01$x}", value, register_size(n) * 2)
+}
+
+// Parse big-endian register bytes.
+fn register_value(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u32)
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Parse `addr,len` into a byte address and length.
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let (address, len) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(len)?))
+}
+
+struct Session<'a> {
+    debugger: &'a mut Debugger,
+    stream: BufReader<TcpStream>,
+}
+
+impl Session<'_> {
+    fn register(&self, n: usize) -> Option<u32> {
+        let vm = &self.debugger.vm;
+        match n {
+            0..=7 => Some(vm.registers[n] as u32),
+            PC => Some(vm.registers[Register::Pc as usize] as u32 * 2),
+            9 => Some(vm.psr() as u32),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u32) -> bool {
+        let vm = &mut self.debugger.vm;
+        match n {
+            0..=7 => vm.registers[n] = value as u16,
+            PC => vm.registers[Register::Pc as usize] = (value / 2) as u16,
+            9 => vm.set_psr(value as u16),
+            _ => return false,
+        }
+        true
+    }
+
+    // Byte of memory at a GDB byte address, device registers are shown as
+    // stored without reading them.
+    fn byte(&self, address: u32) -> u8 {
+        let word = self.debugger.vm.read((address / 2) as usize & 0xFFFF);
+        if address.is_multiple_of(2) {
+            (word >> 8) as u8
+        } else {
+            word as u8
+        }
+    }
+
+    // Write a byte of memory at a GDB byte address, device registers are
+    // written as stored too.
+    fn set_byte(&mut self, address: u32, byte: u8) {
+        let vm = &mut self.debugger.vm;
+        let word_address = (address / 2) as usize & 0xFFFF;
+        let word = vm.read(word_address);
+        let word = if address.is_multiple_of(2) {
+            (word & 0x00FF) | ((byte as u16) << 8)
+        } else {
+            (word & 0xFF00) | byte as u16
+        };
+        vm.poke(word_address, word);
+    }
+
+    // Read the next packet, acknowledging it. Returns `None` once the
+    // connection is closed.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut start = vec![];
+            if self.stream.read_until(b'$', &mut start)? == 0 {
+                return Ok(None);
+            }
+            let mut packet = vec![];
+            self.stream.read_until(b'#', &mut packet)?;
+            let mut checksum = [0; 2];
+            if packet.pop() != Some(b'#')
+                || io::Read::read_exact(&mut self.stream, &mut checksum)
+                    .is_err()
+            {
+                return Ok(None);
+            }
+            let sum = packet.iter().fold(0_u8, |sum, &b| sum.wrapping_add(b));
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+            if expected == Some(sum) {
+                self.stream.get_mut().write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+            }
+            self.stream.get_mut().write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0_u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, sum);
+        self.stream.get_mut().write_all(packet.as_bytes())
+    }
+
+    // Check without blocking whether GDB asked to interrupt the program.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.get_ref().set_nonblocking(true)?;
+        let interrupted = match self.stream.fill_buf() {
+            Ok(buf) => buf.first() == Some(&INTERRUPT),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => false,
+            Err(err) => return Err(err),
+        };
+        if interrupted {
+            self.stream.consume(1);
+        }
+        self.stream.get_ref().set_nonblocking(false)?;
+        Ok(interrupted)
+    }
+
+    // Reply for a stopped or exited program.
+    fn stop_reply(stop: Stop) -> String {
+        match stop {
+            Stop::Done | Stop::Breakpoint(_) | Stop::HistoryStart => {
+                "S05".to_string()
+            }
+            Stop::Halted(RunResult::Halted) => "W00".to_string(),
+            Stop::Halted(RunResult::Unhandled(Exception::IllegalOpcode)) => {
+                "X04".to_string()
+            }
+            Stop::Halted(RunResult::Unhandled(
+                Exception::PrivilegeViolation,
+            )) => "X0b".to_string(),
+        }
+    }
+
+    // Resume at an optional byte address given with `s` or `c`.
+    fn resume_at(&mut self, address: &str) {
+        if let Some(address) = parse_hex(address) {
+            self.debugger.vm.registers[Register::Pc as usize] =
+                (address / 2) as u16;
+        }
+    }
+
+    // Run until a breakpoint, halt or interrupt request.
+    fn resume(&mut self) -> io::Result<String> {
+        let mut steps = 0;
+        loop {
+            let stop = self.debugger.step();
+            if stop != Stop::Done {
+                return Ok(Self::stop_reply(stop));
+            }
+            steps += 1;
+            if steps % INTERRUPT_CHECK == 0 && self.interrupted()? {
+                self.debugger.vm.devices.console.flush();
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    // Answer a `qXfer:features:read:target.xml:offset,length` request.
+    fn target_xml(args: &str) -> String {
+        let Some((offset, len)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+        let start = (offset as usize).min(TARGET_XML.len());
+        let end = start.saturating_add(len as usize).min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+        format!("{}{}", more, &TARGET_XML[start..end])
+    }
+
+    // Handle a packet, returns the reply and whether the session ends.
+    fn handle(&mut self, packet: &str) -> io::Result<(String, Option<End>)> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => (0..REGISTERS)
+                .filter_map(|n| Some(hex_register(n, self.register(n)?)))
+                .collect(),
+            "G" => match parse_hex_bytes(args) {
+                Some(bytes)
+                    if bytes.len()
+                        == (0..REGISTERS).map(register_size).sum() =>
+                {
+                    let mut bytes = bytes.as_slice();
+                    for n in 0..REGISTERS {
+                        let (value, rest) = bytes.split_at(register_size(n));
+                        self.set_register(n, register_value(value));
+                        bytes = rest;
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => {
+                let n = parse_hex(args).map(|n| n as usize);
+                match n.and_then(|n| Some((n, self.register(n)?))) {
+                    Some((n, value)) => hex_register(n, value),
+                    None => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let n = parse_hex(n)?;
+                    let value = parse_hex_bytes(value)?;
+                    (value.len() == register_size(n as usize))
+                        .then(|| (n, register_value(&value)))
+                });
+                match register {
+                    Some((n, value))
+                        if self.set_register(n as usize, value) =>
+                    {
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((address, len)) => (address..address.saturating_add(len))
+                    .map(|address| format!("{:02x}", self.byte(address)))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    Some((parse_range(range)?, parse_hex_bytes(data)?))
+                });
+                match write {
+                    Some(((address, len), data))
+                        if data.len() == len as usize =>
+                    {
+                        for (i, byte) in data.into_iter().enumerate() {
+                            self.set_byte(address + i as u32, byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "s" => {
+                self.resume_at(args);
+                let stop = self.debugger.step();
+                self.debugger.vm.devices.console.flush();
+                Self::stop_reply(stop)
+            }
+            "c" => {
+                self.resume_at(args);
+                self.resume()?
+            }
+            "Z" | "z" => {
+                let breakpoint = args
+                    .strip_prefix("0,")
+                    .or_else(|| args.strip_prefix("1,"))
+                    .and_then(|rest| parse_hex(rest.split(',').next()?));
+                match breakpoint {
+                    Some(address) => {
+                        let address = (address / 2) as u16;
+                        if command == "Z" {
+                            self.debugger.add_breakpoint(address);
+                        } else {
+                            self.debugger.remove_breakpoint(address);
+                        }
+                        "OK".to_string()
+                    }
+                    // Watchpoints aren't supported.
+                    None => String::new(),
+                }
+            }
+            "k" => return Ok((String::new(), Some(End::Killed))),
+            "D" => return Ok(("OK".to_string(), Some(End::Detached))),
+            "H" => "OK".to_string(),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+".to_string()
+            }
+            _ if packet == "qAttached" => "1".to_string(),
+            _ => match packet.strip_prefix("qXfer:features:read:target.xml:") {
+                Some(args) => Self::target_xml(args),
+                None => String::new(),
+            },
+        };
+        let end = (reply.starts_with('W') || reply.starts_with('X'))
+            .then_some(End::Exited);
+        Ok((reply, end))
+    }
+}
+
+// Wait for GDB to connect on `address` and serve it until the session ends.
+// The address is printed once the socket is listening.
+pub fn serve(debugger: &mut Debugger, address: &str) -> io::Result<End> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Waiting for GDB on {}", listener.local_addr()?);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut session = Session {
+        debugger,
+        stream: BufReader::new(stream),
+    };
+    while let Some(packet) = session.read_packet()? {
+        let (reply, end) = session.handle(&packet)?;
+        if end != Some(End::Killed) {
+            session.send(&reply)?;
+        }
+        if let Some(end) = end {
+            return Ok(end);
+        }
+    }
+    // The debugger went away without detaching.
+    Ok(End::Detached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+    use crate::symbols::SymbolTable;
+    use crate::vm::VirtualMachine;
+
+    // Debugger with a program loaded above x8000, where byte addresses no
+    // longer fit in 16 bits.
+    fn debugger() -> Debugger {
+        let program = assemble(
+            ".ORIG x9000\n\
+             ADD R1, R1, #1\n\
+             ADD R1, R1, #2\n\
+             ADD R1, R1, #3\n\
+             HALT\n\
+             .END",
+        )
+        .unwrap();
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")));
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = program.origin;
+        Debugger::new(vm, SymbolTable::new())
+    }
+
+    // Serve packets to a session over a loopback connection.
+    fn session(
+        debugger: &mut Debugger,
+        packets: &[&str],
+    ) -> io::Result<Vec<String>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let _client = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        let mut session = Session {
+            debugger,
+            stream: BufReader::new(stream),
+        };
+        packets
+            .iter()
+            .map(|packet| Ok(session.handle(packet)?.0))
+            .collect()
+    }
+
+    #[test]
+    fn registers() {
+        let mut debugger = debugger();
+        debugger.vm.registers[3] = 0xbeef;
+        let replies = session(&mut debugger, &["g", "p8", "p3"]).unwrap();
+        let g = &replies[0];
+        assert_eq!(g.len(), 9 * 4 + 8);
+        assert_eq!(&g[12..16], "beef");
+        assert_eq!(&g[32..40], "00012000");
+        assert_eq!(replies[1], "00012000");
+        assert_eq!(replies[2], "beef");
+    }
+
+    #[test]
+    fn set_registers() {
+        let mut debugger = debugger();
+        let g = format!("{}{}{}", "0001".repeat(8), "0001fffe", "8002");
+        let replies =
+            session(&mut debugger, &["P8=00012004", "p8", &format!("G{}", g)])
+                .unwrap();
+        assert_eq!(replies, ["OK", "00012004", "OK"]);
+        let vm = &debugger.vm;
+        assert_eq!(vm.registers[Register::Pc as usize], 0xffff);
+        assert_eq!(vm.registers[7], 1);
+        assert_eq!(vm.psr(), 0x8002);
+
+        // Values must be as wide as the register.
+        let replies =
+            session(&mut debugger, &["P8=2004", "P0=00000001", "G0001"])
+                .unwrap();
+        assert_eq!(replies, ["E01", "E01", "E01"]);
+    }
+
+    #[test]
+    fn memory() {
+        let mut debugger = debugger();
+        let replies = session(
+            &mut debugger,
+            &["m12000,4", "M12001,3:aabbcc", "m12000,6"],
+        )
+        .unwrap();
+        assert_eq!(replies[0], "12611262");
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "12aabbcc1263");
+        assert_eq!(debugger.vm.memory[0x9001], 0xbbcc);
+    }
+
+    #[test]
+    fn device_memory() {
+        let console = ScriptedConsole::new(b"");
+        let mut debugger = debugger();
+        debugger.vm.devices.console = Box::new(console.clone());
+        // DDR and MCR are written and read back as stored, nothing is
+        // displayed and the clock keeps running.
+        let replies = session(
+            &mut debugger,
+            &[
+                "M1fc0c,2:0041",
+                "m1fc0c,2",
+                "M1fffc,2:0000",
+                "m1fffc,2",
+                "c",
+            ],
+        )
+        .unwrap();
+        assert_eq!(replies, ["OK", "0041", "OK", "0000", "W00"]);
+        assert_eq!(console.output(), b"\n----- Halting the processor ----- \n");
+        assert_eq!(debugger.vm.registers[1], 6);
+    }
+
+    #[test]
+    fn execution() {
+        let mut debugger = debugger();
+        let replies = session(
+            &mut debugger,
+            &["Z0,12004,2", "c", "p8", "s", "p8", "z0,12004,2", "c"],
+        )
+        .unwrap();
+        assert_eq!(
+            replies,
+            ["OK", "S05", "00012004", "S05", "00012006", "OK", "W00"]
+        );
+        assert_eq!(debugger.vm.registers[1], 6);
+    }
+}
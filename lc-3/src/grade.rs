@@ -0,0 +1,711 @@
+// Headless test harness for grading programs.
+//
+// A spec is a TOML file naming the object files to load and a list of test
+// cases. Each case boots a fresh machine, sets up registers and memory,
+// types its input on the keyboard and runs for at most its instruction
+// budget, then checks the registers, memory and console output:
+//
+//     program = ["sum.obj"]
+//     os = "native"
+//     budget = 100000
+//
+//     [[test]]
+//     name = "sums the array"
+//     input = "5\n"
+//     registers = { R1 = "DATA" }
+//     memory = { DATA = [1, 2, 3, 4, 5] }
+//
+//     [test.expect]
+//     registers = { R0 = 15 }
+//     memory = { x4000 = 15 }
+//     output = "Sum: 15\n"
+//
+// Unknown keys are rejected so a misspelled one can't drop checks. Object
+// files are relative to the spec. Values are integers or strings holding a
+// number in assembler syntax or a label from the `.sym` files next to the
+// object files. Results can be written as JUnit XML for CI.
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use toml::{Table, Value};
+
+use crate::asm::parse_number;
+use crate::console::ScriptedConsole;
+use crate::loader::LoadError;
+use crate::os::OsMode;
+use crate::symbols::SymbolTable;
+use crate::vm::{CondFlags, Register, RunResult, VirtualMachine, MEMORY_MAX};
+
+// Instructions a test may execute when the spec doesn't set a budget.
+pub const DEFAULT_BUDGET: u64 = 1_000_000;
+
+#[derive(Debug)]
+pub enum SpecError {
+    // The spec isn't valid TOML.
+    Toml(toml::de::Error),
+    // A key is missing or holds a value of the wrong kind.
+    Invalid(String),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::Toml(err) => write!(f, "{}", err),
+            SpecError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for SpecError {}
+
+// Registers and memory words, set before a test runs or expected after.
+#[derive(Clone, Debug, Default)]
+pub struct State {
+    pub registers: Vec<(Register, u16)>,
+    // Words starting at an address.
+    pub memory: Vec<(u16, Vec<u16>)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Case {
+    pub name: String,
+    // Instructions executed before the test fails for not halting.
+    pub budget: u64,
+    // Keys typed on the keyboard.
+    pub input: Vec<u8>,
+    pub setup: State,
+    pub expect: State,
+    // Everything the program should display, unchecked when `None`.
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Spec {
+    // Object files loaded in order, the first one runs.
+    pub program: Vec<String>,
+    pub os: OsMode,
+    pub cases: Vec<Case>,
+}
+
+// Result of running a test case.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub name: String,
+    // Instructions executed.
+    pub steps: u64,
+    pub time: Duration,
+    // Why the test failed, empty if it passed.
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+fn invalid<T>(message: String) -> Result<T, SpecError> {
+    Err(SpecError::Invalid(message))
+}
+
+// Resolve a number or label to a word.
+fn word(
+    value: &Value,
+    symbols: &SymbolTable,
+    key: &str,
+) -> Result<u16, SpecError> {
+    let number = match value {
+        Value::Integer(n) => Some(*n),
+        Value::String(token) => symbols
+            .get(token)
+            .map(i64::from)
+            .or_else(|| parse_number(token).map(i64::from)),
+        _ => None,
+    };
+    match number {
+        Some(n) if (-0x8000..=0xFFFF).contains(&n) => Ok(n as u16),
+        _ => invalid(format!("{} : {} isn't a word or a label", key, value)),
+    }
+}
+
+fn register(name: &str) -> Option<Register> {
+    let register = match name.to_ascii_uppercase().as_str() {
+        "R0" => Register::R0,
+        "R1" => Register::R1,
+        "R2" => Register::R2,
+        "R3" => Register::R3,
+        "R4" => Register::R4,
+        "R5" => Register::R5,
+        "R6" => Register::R6,
+        "R7" => Register::R7,
+        "PC" => Register::Pc,
+        _ => return None,
+    };
+    Some(register)
+}
+
+fn register_name(r: Register) -> String {
+    match r {
+        Register::Pc => "PC".to_string(),
+        r => format!("{:?}", r),
+    }
+}
+
+fn string<'a>(
+    table: &'a Table,
+    key: &str,
+) -> Result<Option<&'a str>, SpecError> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => invalid(format!("{} should be a string", key)),
+    }
+}
+
+fn table<'a>(
+    table: &'a Table,
+    key: &str,
+) -> Result<Option<&'a Table>, SpecError> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Table(t)) => Ok(Some(t)),
+        Some(_) => invalid(format!("{} should be a table", key)),
+    }
+}
+
+fn budget(table: &Table, default: u64) -> Result<u64, SpecError> {
+    match table.get("budget") {
+        None => Ok(default),
+        Some(Value::Integer(n)) if *n > 0 => Ok(*n as u64),
+        Some(_) => invalid("budget should be a positive integer".to_string()),
+    }
+}
+
+// Reject keys other than `known`, a misspelled key would silently drop the
+// checks it holds. `prefix` is the path of the table.
+fn known_keys(
+    table: &Table,
+    known: &[&str],
+    prefix: &str,
+) -> Result<(), SpecError> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => invalid(format!("unknown key {}{}", prefix, key)),
+        None => Ok(()),
+    }
+}
+
+// Read the `registers` and `memory` tables of a test or its expectations.
+fn state(t: &Table, symbols: &SymbolTable) -> Result<State, SpecError> {
+    let mut state = State::default();
+    for (name, value) in table(t, "registers")?.into_iter().flatten() {
+        let Some(r) = register(name) else {
+            return invalid(format!("{} isn't a register", name));
+        };
+        state.registers.push((r, word(value, symbols, name)?));
+    }
+    for (location, value) in table(t, "memory")?.into_iter().flatten() {
+        let address =
+            word(&Value::String(location.clone()), symbols, location)?;
+        let words = match value {
+            Value::Array(values) => values
+                .iter()
+                .map(|v| word(v, symbols, location))
+                .collect::<Result<_, _>>()?,
+            value => vec![word(value, symbols, location)?],
+        };
+        if address as usize + words.len() > MEMORY_MAX {
+            return invalid(format!(
+                "{} : words run past the end of memory",
+                location
+            ));
+        }
+        state.memory.push((address, words));
+    }
+    Ok(state)
+}
+
+impl Spec {
+    // Read a spec, object files are relative to `dir`. Labels are looked up
+    // in the `.sym` files next to the object files.
+    pub fn parse(text: &str, dir: &Path) -> Result<Spec, SpecError> {
+        let spec: Table = text.parse().map_err(SpecError::Toml)?;
+        known_keys(&spec, &["program", "os", "budget", "test"], "")?;
+        let program = match spec.get("program") {
+            Some(Value::Array(files)) if !files.is_empty() => files
+                .iter()
+                .map(|file| match file {
+                    Value::String(file) => {
+                        Ok(dir.join(file).to_string_lossy().into_owned())
+                    }
+                    _ => invalid("program should list file names".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => {
+                return invalid(
+                    "program should list the object files".to_string(),
+                )
+            }
+        };
+        let os = match string(&spec, "os")? {
+            None | Some("native") => OsMode::Native,
+            Some("image") => OsMode::Image,
+            Some(os) => {
+                return invalid(format!("{} isn't native or image", os))
+            }
+        };
+        let default_budget = budget(&spec, DEFAULT_BUDGET)?;
+        let symbols = SymbolTable::for_objects(&program);
+
+        let tests = match spec.get("test") {
+            None => vec![],
+            Some(Value::Array(tests)) => tests.iter().collect(),
+            Some(_) => {
+                return invalid("test should be an array of tables".to_string())
+            }
+        };
+        let mut cases = vec![];
+        for (i, test) in tests.into_iter().enumerate() {
+            let Value::Table(test) = test else {
+                return invalid(
+                    "test should be an array of tables".to_string(),
+                );
+            };
+            let name = string(test, "name")?
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("test {}", i + 1));
+            let at = |err| match err {
+                SpecError::Invalid(message) => {
+                    SpecError::Invalid(format!("{} : {}", name, message))
+                }
+                err => err,
+            };
+            known_keys(
+                test,
+                &["name", "budget", "input", "registers", "memory", "expect"],
+                "",
+            )
+            .map_err(at)?;
+            let expect = table(test, "expect").map_err(at)?;
+            if let Some(expect) = expect {
+                known_keys(
+                    expect,
+                    &["registers", "memory", "output"],
+                    "expect.",
+                )
+                .map_err(at)?;
+            }
+            cases.push(Case {
+                budget: budget(test, default_budget).map_err(at)?,
+                input: string(test, "input")
+                    .map_err(at)?
+                    .unwrap_or_default()
+                    .as_bytes()
+                    .to_vec(),
+                setup: state(test, &symbols).map_err(at)?,
+                expect: match expect {
+                    Some(expect) => state(expect, &symbols).map_err(at)?,
+                    None => State::default(),
+                },
+                output: match expect {
+                    Some(expect) => string(expect, "output")
+                        .map_err(at)?
+                        .map(str::to_string),
+                    None => None,
+                },
+                name,
+            });
+        }
+        Ok(Spec { program, os, cases })
+    }
+
+    // Boot a fresh machine, run a test case on it and check the results.
+    pub fn run(&self, case: &Case) -> Result<Outcome, LoadError> {
+        let console = ScriptedConsole::new(&case.input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        vm.registers[Register::Cond as usize] = CondFlags::Zero as u16;
+        let mut loaded = vec![];
+        if self.os == OsMode::Image {
+            loaded.push(vm.load_os());
+        }
+        vm.load_files(&self.program, &loaded)?;
+        for &(r, value) in &case.setup.registers {
+            vm.registers[r as usize] = value;
+        }
+        for (address, words) in &case.setup.memory {
+            let start = *address as usize;
+            for address in start..start + words.len() {
+                vm.memory_written(address as u16);
+            }
+            vm.memory[start..start + words.len()].copy_from_slice(words);
+        }
+
+        let start = Instant::now();
+        let mut steps = 0;
+        while !vm.halted && steps < case.budget {
+            vm.step();
+            steps += 1;
+        }
+        let time = start.elapsed();
+
+        let mut failures = vec![];
+        let pc = vm.registers[Register::Pc as usize];
+        if !vm.halted {
+            failures.push(format!(
+                "didn't halt within {} instructions, PC is x{:04X}",
+                case.budget, pc
+            ));
+        } else if let RunResult::Unhandled(exception) = vm.result() {
+            failures.push(format!(
+                "unhandled exception {:?} before x{:04X}",
+                exception, pc
+            ));
+        }
+        for &(r, expected) in &case.expect.registers {
+            let actual = vm.registers[r as usize];
+            if actual != expected {
+                failures.push(format!(
+                    "{} is x{:04X}, expected x{:04X}",
+                    register_name(r),
+                    actual,
+                    expected
+                ));
+            }
+        }
+        for (address, words) in &case.expect.memory {
+            for (offset, &expected) in words.iter().enumerate() {
+                let address = address + offset as u16;
+                let actual = vm.read(address as usize);
+                if actual != expected {
+                    failures.push(format!(
+                        "x{:04X} is x{:04X}, expected x{:04X}",
+                        address, actual, expected
+                    ));
+                }
+            }
+        }
+        if let Some(expected) = &case.output {
+            let output =
+                String::from_utf8_lossy(&console.output()).into_owned();
+            if output != *expected {
+                failures.push(format!(
+                    "output is {:?}, expected {:?}",
+                    output, expected
+                ));
+            }
+        }
+        Ok(Outcome {
+            name: case.name.clone(),
+            steps,
+            time,
+            failures,
+        })
+    }
+}
+
+// Escape text for XML attributes and content.
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0.
+            c if c.is_control() && !matches!(c, '\n' | '\t') => {
+                out.push_str(&format!("\\u{{{:x}}}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Render outcomes as a JUnit XML report with one test suite.
+pub fn junit(suite: &str, outcomes: &[Outcome]) -> String {
+    let failed = outcomes.iter().filter(|o| !o.passed()).count();
+    let time: Duration = outcomes.iter().map(|o| o.time).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.6}\">\n",
+        escape(suite),
+        outcomes.len(),
+        failed,
+        time.as_secs_f64()
+    ));
+    for outcome in outcomes {
+        out.push_str(&format!(
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+            escape(&outcome.name),
+            escape(suite),
+            outcome.time.as_secs_f64()
+        ));
+        if outcome.passed() {
+            out.push_str("/>\n");
+            continue;
+        }
+        out.push_str(">\n");
+        out.push_str(&format!(
+            "    <failure message=\"{}\">{}</failure>\n",
+            escape(&outcome.failures[0]),
+            escape(&outcome.failures.join("\n"))
+        ));
+        out.push_str("  </testcase>\n");
+    }
+    out.push_str("</testsuite>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SUM: &str = "        .ORIG x3000
+        AND R0, R0, #0
+        LD R2, COUNT
+LOOP    LDR R3, R1, #0
+        ADD R0, R0, R3
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp LOOP
+        STI R0, RESULT
+        HALT
+COUNT   .FILL #5
+RESULT  .FILL x4000
+DATA    .BLKW #5
+        .END";
+
+    // Assemble the sum program with its symbol table into a fresh
+    // directory.
+    fn directory() -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("lc3-grade-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = assemble(SUM).unwrap();
+        fs::write(dir.join("sum.obj"), program.to_obj()).unwrap();
+        fs::write(dir.join("sum.sym"), program.symbols.to_sym()).unwrap();
+        dir
+    }
+
+    fn error(text: &str) -> String {
+        match Spec::parse(text, Path::new(".")) {
+            Ok(spec) => panic!("{:?} parsed", spec),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_and_runs_spec() {
+        let dir = directory();
+        let spec = Spec::parse(
+            r##"
+            program = ["sum.obj"]
+            budget = 500
+
+            [[test]]
+            name = "sums the array"
+            registers = { R1 = "DATA" }
+            memory = { DATA = [1, 2, 3, 4, "x5"] }
+
+            [test.expect]
+            registers = { R0 = 15, R2 = 0 }
+            memory = { x4000 = "#15" }
+            output = "\n----- Halting the processor ----- \n"
+
+            [[test]]
+            budget = 10
+            registers = { R1 = "DATA" }
+            "##,
+            &dir,
+        )
+        .unwrap();
+        assert_eq!(spec.os, OsMode::Native);
+        assert_eq!(spec.cases.len(), 2);
+        let sums = &spec.cases[0];
+        assert_eq!(sums.budget, 500);
+        assert!(matches!(sums.setup.registers[..], [(Register::R1, 0x300B)]));
+        assert_eq!(sums.setup.memory, [(0x300B, vec![1, 2, 3, 4, 5])]);
+        assert_eq!(sums.expect.memory, [(0x4000, vec![15])]);
+        let outcome = spec.run(sums).unwrap();
+        assert!(outcome.passed(), "{:?}", outcome.failures);
+
+        let short = &spec.cases[1];
+        assert_eq!(short.name, "test 2");
+        assert_eq!(short.budget, 10);
+        let outcome = spec.run(short).unwrap();
+        assert_eq!(outcome.steps, 10);
+        assert_eq!(
+            outcome.failures,
+            ["didn't halt within 10 instructions, PC is x3005"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_toml() {
+        assert!(matches!(
+            Spec::parse("program = [", Path::new(".")),
+            Err(SpecError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_program() {
+        assert_eq!(
+            error("os = \"native\""),
+            "program should list the object files"
+        );
+        assert_eq!(
+            error("program = []"),
+            "program should list the object files"
+        );
+        assert_eq!(error("program = [1]"), "program should list file names");
+    }
+
+    #[test]
+    fn invalid_os() {
+        assert_eq!(
+            error("program = [\"a.obj\"]\nos = \"dos\""),
+            "dos isn't native or image"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\nos = 1"),
+            "os should be a string"
+        );
+    }
+
+    #[test]
+    fn invalid_budget() {
+        assert_eq!(
+            error("program = [\"a.obj\"]\nbudget = 0"),
+            "budget should be a positive integer"
+        );
+        assert_eq!(
+            error(
+                "program = [\"a.obj\"]\n[[test]]\nname = \"t\"\nbudget = \"x\""
+            ),
+            "t : budget should be a positive integer"
+        );
+    }
+
+    #[test]
+    fn invalid_tests() {
+        assert_eq!(
+            error("program = [\"a.obj\"]\ntest = 1"),
+            "test should be an array of tables"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[test]]\nregisters = { R9 = 1 }"),
+            "test 1 : R9 isn't a register"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[test]]\nregisters = 1"),
+            "test 1 : registers should be a table"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[test]]\ninput = 1"),
+            "test 1 : input should be a string"
+        );
+    }
+
+    #[test]
+    fn invalid_words() {
+        let spec = |test: &str| {
+            error(&format!("program = [\"a.obj\"]\n[[test]]\n{}", test))
+        };
+        assert_eq!(
+            spec("registers = { R0 = 65536 }"),
+            "test 1 : R0 : 65536 isn't a word or a label"
+        );
+        assert_eq!(
+            spec("registers = { R0 = -32769 }"),
+            "test 1 : R0 : -32769 isn't a word or a label"
+        );
+        assert_eq!(
+            spec("registers = { R0 = \"NOWHERE\" }"),
+            "test 1 : R0 : \"NOWHERE\" isn't a word or a label"
+        );
+        assert_eq!(
+            spec("memory = { NOWHERE = 1 }"),
+            "test 1 : NOWHERE : \"NOWHERE\" isn't a word or a label"
+        );
+        assert_eq!(
+            spec("memory = { xFFFF = [1, 2] }"),
+            "test 1 : xFFFF : words run past the end of memory"
+        );
+        assert_eq!(
+            spec("[test.expect]\nmemory = { x4000 = 1.5 }"),
+            "test 1 : x4000 : 1.5 isn't a word or a label"
+        );
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(
+            error("program = [\"a.obj\"]\nbudgte = 10"),
+            "unknown key budgte"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[tests]]\nname = \"t\""),
+            "unknown key tests"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[test]]\nname = \"t\"\n[test.expected]"),
+            "t : unknown key expected"
+        );
+        assert_eq!(
+            error(
+                "program = [\"a.obj\"]\n[[test]]\n\
+                 [test.expect]\nregister = { R0 = 1 }"
+            ),
+            "test 1 : unknown key expect.register"
+        );
+        assert_eq!(
+            error("program = [\"a.obj\"]\n[[test]]\nexpect.outptu = \"\""),
+            "test 1 : unknown key expect.outptu"
+        );
+    }
+
+    fn outcome(name: &str, failures: &[&str]) -> Outcome {
+        Outcome {
+            name: name.to_string(),
+            steps: 0,
+            time: Duration::from_millis(250),
+            failures: failures.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn junit_report() {
+        let report = junit(
+            "sum & <co>",
+            &[
+                outcome("passes \"quoted\"", &[]),
+                outcome(
+                    "it's failing",
+                    &["R0 is x0001 <1>", "output \u{7}\tbell\n"],
+                ),
+            ],
+        );
+        assert_eq!(
+            report,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"sum &amp; &lt;co&gt;\" tests=\"2\" \
+             failures=\"1\" time=\"0.500000\">\n  \
+             <testcase name=\"passes &quot;quoted&quot;\" \
+             classname=\"sum &amp; &lt;co&gt;\" time=\"0.250000\"/>\n  \
+             <testcase name=\"it&apos;s failing\" \
+             classname=\"sum &amp; &lt;co&gt;\" time=\"0.250000\">\n    \
+             <failure message=\"R0 is x0001 &lt;1&gt;\">R0 is x0001 \
+             &lt;1&gt;\noutput \\u{7}\tbell\n</failure>\n  \
+             </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+}
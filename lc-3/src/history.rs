@@ -0,0 +1,303 @@
+// Undo log for reverse execution.
+//
+// While enabled, every step records the machine state it is about to change:
+// the registers, PSR, saved stack pointers, halt state and device registers
+// up front and the previous value of every memory word written. Stepping
+// back restores them, newest first. The log is a ring buffer keeping the
+// most recent steps.
+//
+// The console isn't rewound, characters read or displayed stay consumed.
+use std::collections::VecDeque;
+
+use crate::devices::DeviceState;
+use crate::interrupts::Exception;
+use crate::vm::{Register, VirtualMachine};
+
+// State changed by a single step.
+#[derive(Clone, Debug)]
+struct Undo {
+    registers: [u16; Register::Count as usize],
+    psr: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+    halted: bool,
+    exception: Option<Exception>,
+    devices: DeviceState,
+    // Addresses written and their previous values, in write order.
+    writes: Vec<(u16, u16)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct History {
+    entries: VecDeque<Undo>,
+    capacity: usize,
+}
+
+impl History {
+    // Create a log keeping at most `capacity` steps.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Number of steps that can be undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Forget all recorded steps.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Record the previous value of a memory word written by the current
+    // step.
+    pub fn write(&mut self, address: u16, old: u16) {
+        if let Some(undo) = self.entries.back_mut() {
+            undo.writes.push((address, old));
+        }
+    }
+}
+
+impl VirtualMachine {
+    // Start recording the last `capacity` steps so they can be undone.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    // Record the state before a step, called by `step`.
+    pub fn record_step(&mut self) {
+        let undo = Undo {
+            registers: self.registers,
+            psr: self.psr,
+            saved_usp: self.saved_usp,
+            saved_ssp: self.saved_ssp,
+            halted: self.halted,
+            exception: self.exception,
+            devices: self.devices.state(),
+            writes: vec![],
+        };
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        if history.capacity == 0 {
+            return;
+        }
+        if history.entries.len() == history.capacity {
+            history.entries.pop_front();
+        }
+        history.entries.push_back(undo);
+    }
+
+    // Undo the last recorded step, returns false if there is none.
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) =
+            self.history.as_mut().and_then(|h| h.entries.pop_back())
+        else {
+            return false;
+        };
+        for &(address, old) in undo.writes.iter().rev() {
+            self.memory_written(address);
+            self.memory[address as usize] = old;
+        }
+        self.registers = undo.registers;
+        self.psr = undo.psr;
+        self.saved_usp = undo.saved_usp;
+        self.saved_ssp = undo.saved_ssp;
+        self.halted = undo.halted;
+        self.exception = undo.exception;
+        self.devices.set_state(undo.devices);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+    use crate::interrupts::{IVT_BASE, PSR_USER};
+    use crate::vm::RunResult;
+
+    // State restored by stepping back.
+    type State = ([u16; Register::Count as usize], u16, u16, u16, Vec<u16>);
+
+    fn state(vm: &VirtualMachine) -> State {
+        (
+            vm.registers,
+            vm.psr(),
+            vm.saved_usp,
+            vm.saved_ssp,
+            vm.memory.to_vec(),
+        )
+    }
+
+    // Machine running `source` in user mode with an illegal opcode handler
+    // at x4000.
+    fn machine(source: &str, capacity: usize) -> VirtualMachine {
+        let program = assemble(source).unwrap();
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")));
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.memory[(IVT_BASE + Exception::IllegalOpcode as u16) as usize] =
+            0x4000;
+        vm.registers[Register::Pc as usize] = program.origin;
+        vm.registers[Register::R6 as usize] = 0xFE00;
+        vm.set_psr(PSR_USER | 0x2);
+        vm.enable_history(capacity);
+        vm
+    }
+
+    const PROGRAM: &str = "        .ORIG x3000
+        ADD R0, R0, #5
+        ST R0, DATA
+        STR R0, R0, #0
+        .FILL xD000
+DATA    .FILL #7
+        .END";
+
+    #[test]
+    fn step_back_restores_state() {
+        let mut vm = machine(PROGRAM, 10);
+        let mut states = vec![];
+        for _ in 0..4 {
+            states.push(state(&vm));
+            vm.step();
+        }
+        // The illegal opcode switched to the supervisor stack and pushed PSR
+        // and PC on it.
+        assert!(!vm.user_mode());
+        assert_eq!(vm.registers[Register::Pc as usize], 0x4000);
+        assert_eq!(vm.registers[Register::R6 as usize], 0x2FFE);
+        assert_eq!(vm.saved_usp, 0xFE00);
+        assert_eq!(vm.memory[0x2FFF], PSR_USER | 0x1);
+        assert_eq!(vm.memory[0x3004], 5);
+        assert_eq!(vm.memory[0x0005], 5);
+        assert_eq!(vm.history.as_ref().unwrap().len(), 4);
+        while let Some(expected) = states.pop() {
+            assert!(vm.step_back());
+            assert!(state(&vm) == expected, "{} steps", states.len());
+        }
+        assert!(!vm.step_back());
+    }
+
+    #[test]
+    fn step_back_restores_halt() {
+        let mut vm = machine(".ORIG x3000\nHALT\n.END", 10);
+        vm.set_psr(0x2);
+        vm.step();
+        assert!(vm.halted);
+        assert!(vm.step_back());
+        assert!(!vm.halted);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+    }
+
+    #[test]
+    fn step_back_restores_exception() {
+        let mut vm = machine(".ORIG x3000\n.FILL xD000\n.END", 10);
+        vm.memory[(IVT_BASE + Exception::IllegalOpcode as u16) as usize] = 0;
+        vm.step();
+        assert_eq!(vm.exception, Some(Exception::IllegalOpcode));
+        assert!(vm.step_back());
+        assert_eq!(vm.exception, None);
+        assert!(!vm.halted);
+    }
+
+    #[test]
+    fn capacity_keeps_newest_steps() {
+        let mut vm = machine(
+            ".ORIG x3000\nLOOP ADD R0, R0, #1\nST R0, DATA\nBR LOOP\n\
+             DATA .FILL #0\n.END",
+            4,
+        );
+        for _ in 0..9 {
+            vm.step();
+        }
+        assert_eq!(vm.registers[Register::R0 as usize], 3);
+        assert_eq!(vm.memory[0x3003], 3);
+        assert_eq!(vm.history.as_ref().unwrap().len(), 4);
+        for _ in 0..4 {
+            assert!(vm.step_back());
+        }
+        assert!(!vm.step_back());
+        // Back before the sixth step, the older writes stay.
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3002);
+        assert_eq!(vm.registers[Register::R0 as usize], 2);
+        assert_eq!(vm.memory[0x3003], 2);
+    }
+
+    #[test]
+    fn zero_capacity_records_nothing() {
+        let mut vm = machine(PROGRAM, 0);
+        vm.step();
+        assert!(vm.history.as_ref().unwrap().is_empty());
+        assert!(!vm.step_back());
+    }
+
+    #[test]
+    fn step_back_over_os_halt() {
+        let mut vm = machine(".ORIG x3000\nHALT\n.END", 10);
+        vm.load_os();
+        vm.set_psr(0x2);
+        while !vm.halted {
+            vm.step();
+        }
+        assert!(!vm.devices.running());
+        // Back to the LDI reading MCR in the halt routine.
+        for _ in 0..4 {
+            assert!(vm.step_back());
+        }
+        assert!(!vm.halted);
+        assert!(vm.devices.running());
+        vm.step();
+        assert!(!vm.halted);
+        assert_eq!(vm.run(), RunResult::Halted);
+    }
+
+    #[test]
+    fn step_back_restores_keyboard() {
+        let program = assemble(
+            ".ORIG x3000\nLDI R1, KBSR\nLDI R0, KBDR\n\
+             KBSR .FILL xFE00\nKBDR .FILL xFE02\n.END",
+        )
+        .unwrap();
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"a")));
+        vm.load_image("test", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.enable_history(10);
+        vm.step();
+        assert_eq!(vm.registers[Register::R1 as usize], 1 << 15);
+        vm.step();
+        assert_eq!(vm.registers[Register::R0 as usize], b'a' as u16);
+        assert!(!vm.devices.state().key_ready);
+        // The key read from KBDR is ready again once the read is undone,
+        // although the console has no input left.
+        assert!(vm.step_back());
+        assert!(vm.devices.state().key_ready);
+        assert_eq!(vm.registers[Register::R0 as usize], 0);
+        vm.step();
+        assert_eq!(vm.registers[Register::R0 as usize], b'a' as u16);
+    }
+
+    #[test]
+    fn poke_is_not_undone() {
+        let mut vm = machine(PROGRAM, 10);
+        vm.step();
+        vm.poke(0x3004, 0x1234);
+        vm.step();
+        assert_eq!(vm.memory[0x3004], 5);
+        assert!(vm.step_back());
+        assert_eq!(vm.memory[0x3004], 0x1234);
+        vm.poke(0x4000, 0xBEEF);
+        assert!(vm.step_back());
+        assert_eq!(vm.memory[0x3004], 0x1234);
+        assert_eq!(vm.memory[0x4000], 0xBEEF);
+    }
+}
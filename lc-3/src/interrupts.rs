@@ -0,0 +1,259 @@
+// Privilege modes, exceptions and the supervisor stack.
+//
+// The processor status register holds the privilege mode in bit 15, the
+// priority level in bits [10:8] and the condition codes in bits [2:0]. The
+// condition codes live in the `Cond` register, the other bits are kept in
+// `VirtualMachine::psr`.
+//
+// Exceptions and device interrupts switch to the supervisor stack, push PSR
+// and PC and jump to the handler found in the interrupt vector table. RTI pops
+// them back.
+use crate::vm::{Register, VirtualMachine};
+
+// Privilege bit of the PSR, set in user mode.
+pub const PSR_USER: u16 = 1 << 15;
+// Priority level bits of the PSR.
+pub const PSR_PRIORITY: u16 = 0x7 << 8;
+// Condition code bits of the PSR.
+pub const PSR_CC: u16 = 0x7;
+// Base of the interrupt vector table.
+pub const IVT_BASE: u16 = 0x0100;
+// Initial supervisor stack pointer, the stack grows down from the start of
+// user space.
+pub const INITIAL_SSP: u16 = 0x3000;
+
+// Exceptions and their vectors in the interrupt vector table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exception {
+    // RTI executed in user mode.
+    PrivilegeViolation = 0x00,
+    // The reserved opcode 1101 was executed.
+    IllegalOpcode = 0x01,
+}
+
+impl VirtualMachine {
+    // Processor status register.
+    pub fn psr(&self) -> u16 {
+        (self.psr & !PSR_CC)
+            | (self.registers[Register::Cond as usize] & PSR_CC)
+    }
+
+    // Replace the processor status register.
+    pub fn set_psr(&mut self, psr: u16) {
+        self.psr = psr & (PSR_USER | PSR_PRIORITY);
+        self.registers[Register::Cond as usize] = psr & PSR_CC;
+    }
+
+    // Whether the processor runs in user mode.
+    pub fn user_mode(&self) -> bool {
+        self.psr & PSR_USER != 0
+    }
+
+    // Push a word on the stack pointed to by R6.
+    fn push(&mut self, value: u16) {
+        let sp = self.registers[Register::R6 as usize].wrapping_sub(1);
+        self.registers[Register::R6 as usize] = sp;
+        self.mem_write(sp as usize, value);
+    }
+
+    // Pop a word off the stack pointed to by R6.
+    fn pop(&mut self) -> u16 {
+        let sp = self.registers[Register::R6 as usize];
+        self.registers[Register::R6 as usize] = sp.wrapping_add(1);
+        self.mem_read(sp as usize)
+    }
+
+    // Switch to the supervisor stack, save PSR and PC and jump through the
+    // interrupt vector table entry `vector`. `priority` raises the priority
+    // level for interrupts, exceptions keep the current one.
+    pub fn enter_supervisor(&mut self, vector: u8, priority: Option<u16>) {
+        let psr = self.psr();
+        if self.user_mode() {
+            self.saved_usp = self.registers[Register::R6 as usize];
+            self.registers[Register::R6 as usize] = self.saved_ssp;
+        }
+        self.push(psr);
+        self.push(self.registers[Register::Pc as usize]);
+        self.psr &= !PSR_USER;
+        if let Some(priority) = priority {
+            self.psr = (self.psr & !PSR_PRIORITY) | ((priority & 0x7) << 8);
+        }
+        self.registers[Register::Pc as usize] =
+            self.mem_read((IVT_BASE + vector as u16) as usize);
+    }
+
+    // Raise an exception. Without a handler in the vector table the machine
+    // halts and reports the exception instead of jumping to address zero.
+    pub fn raise(&mut self, exception: Exception) {
+        if self.memory[(IVT_BASE + exception as u16) as usize] == 0 {
+            self.exception = Some(exception);
+            self.halted = true;
+            return;
+        }
+        self.enter_supervisor(exception as u8, None);
+    }
+
+    // Check for device interrupts between instructions. An interrupt is taken
+    // when its priority is above the current priority level and a handler is
+    // installed, otherwise it stays pending. Returns whether one was taken.
+    pub fn check_interrupts(&mut self) -> bool {
+        let Some((vector, priority)) = self.devices.pending_interrupt() else {
+            return false;
+        };
+        let current = (self.psr & PSR_PRIORITY) >> 8;
+        let handler = self.memory[(IVT_BASE + vector as u16) as usize];
+        if priority > current && handler != 0 {
+            self.enter_supervisor(vector, Some(priority));
+            return true;
+        }
+        false
+    }
+
+    // Return from interrupt, restores PC and PSR from the supervisor stack
+    // and switches back to the user stack when returning to user mode.
+    pub fn rti(&mut self) {
+        if self.user_mode() {
+            self.raise(Exception::PrivilegeViolation);
+            return;
+        }
+        let pc = self.pop();
+        let psr = self.pop();
+        self.registers[Register::Pc as usize] = pc;
+        self.set_psr(psr);
+        if self.user_mode() {
+            self.saved_ssp = self.registers[Register::R6 as usize];
+            self.registers[Register::R6 as usize] = self.saved_usp;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+    use crate::devices::{KBDR, KBSR, KEYBOARD_VECTOR};
+
+    const HANDLER: u16 = 0x1000;
+    const USER_STACK: u16 = 0xFE00;
+
+    // User program counting in R1, with a keyboard handler storing the key
+    // in R0. Keyboard interrupts are enabled when `enabled` is set.
+    fn machine(input: &[u8], enabled: bool) -> VirtualMachine {
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(input)));
+        let user = assemble(".ORIG x3000\nLOOP ADD R1, R1, #1\nBR LOOP\n.END")
+            .unwrap();
+        vm.load_image("user", &user.to_obj()).unwrap();
+        let handler =
+            assemble(".ORIG x1000\nLDI R0, DATA\nRTI\nDATA .FILL xFE02\n.END")
+                .unwrap();
+        vm.load_image("handler", &handler.to_obj()).unwrap();
+        vm.memory[(IVT_BASE + KEYBOARD_VECTOR as u16) as usize] = HANDLER;
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.registers[Register::R6 as usize] = USER_STACK;
+        vm.set_psr(PSR_USER | 0x2);
+        if enabled {
+            vm.mem_write(KBSR as usize, 1 << 14);
+        }
+        vm
+    }
+
+    #[test]
+    fn keyboard_interrupt_delivered() {
+        let mut vm = machine(b"k", true);
+        vm.check_interrupts();
+        assert_eq!(vm.registers[Register::Pc as usize], HANDLER);
+        assert!(!vm.user_mode());
+        assert_eq!(vm.psr() & PSR_PRIORITY, 4 << 8);
+        // PSR and PC were pushed on the supervisor stack.
+        assert_eq!(vm.saved_usp, USER_STACK);
+        assert_eq!(vm.registers[Register::R6 as usize], INITIAL_SSP - 2);
+        assert_eq!(vm.memory[INITIAL_SSP as usize - 1], PSR_USER | 0x2);
+        assert_eq!(vm.memory[INITIAL_SSP as usize - 2], 0x3000);
+    }
+
+    #[test]
+    fn rti_returns_to_user_mode() {
+        let mut vm = machine(b"k", true);
+        // The interrupt is taken before the step runs the first handler
+        // instruction.
+        vm.step();
+        assert_eq!(vm.registers[Register::Pc as usize], HANDLER + 1);
+        assert_eq!(vm.registers[Register::R0 as usize], b'k' as u16);
+        // The ADD at x3000 hasn't run yet.
+        assert_eq!(vm.registers[Register::R1 as usize], 0);
+        vm.step();
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+        assert!(vm.user_mode());
+        assert_eq!(vm.psr(), PSR_USER | 0x2);
+        assert_eq!(vm.registers[Register::R6 as usize], USER_STACK);
+        assert_eq!(vm.saved_ssp, INITIAL_SSP);
+        vm.step();
+        assert_eq!(vm.registers[Register::R1 as usize], 1);
+    }
+
+    #[test]
+    fn disabled_keyboard_interrupt_not_delivered() {
+        let mut vm = machine(b"k", false);
+        for _ in 0..4 {
+            vm.step();
+        }
+        assert!(vm.user_mode());
+        assert_eq!(vm.registers[Register::R1 as usize], 2);
+        // The key is still waiting to be read.
+        assert_eq!(vm.mem_read(KBSR as usize), 1 << 15);
+        assert_eq!(vm.mem_read(KBDR as usize), b'k' as u16);
+    }
+
+    #[test]
+    fn priority_masks_interrupt() {
+        for priority in [4, 5, 7] {
+            let mut vm = machine(b"k", true);
+            vm.set_psr(PSR_USER | (priority << 8) | 0x2);
+            vm.check_interrupts();
+            assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+            assert!(vm.user_mode());
+            assert_eq!(vm.registers[Register::R6 as usize], USER_STACK);
+        }
+        // Taken once the priority level drops below the keyboard's.
+        let mut vm = machine(b"k", true);
+        vm.set_psr(PSR_USER | (4 << 8) | 0x2);
+        vm.check_interrupts();
+        vm.set_psr(PSR_USER | (3 << 8) | 0x2);
+        vm.check_interrupts();
+        assert_eq!(vm.registers[Register::Pc as usize], HANDLER);
+        assert_eq!(vm.memory[INITIAL_SSP as usize - 1], PSR_USER | 0x302);
+    }
+
+    #[test]
+    fn interrupt_needs_handler_and_key() {
+        let mut vm = machine(b"", true);
+        vm.check_interrupts();
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+
+        let mut vm = machine(b"k", true);
+        vm.memory[(IVT_BASE + KEYBOARD_VECTOR as u16) as usize] = 0;
+        vm.check_interrupts();
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+        assert!(vm.user_mode());
+    }
+
+    #[test]
+    fn supervisor_mode_keeps_stack() {
+        let mut vm = machine(b"k", true);
+        vm.set_psr(0x2);
+        vm.registers[Register::R6 as usize] = 0x2F00;
+        vm.check_interrupts();
+        assert_eq!(vm.registers[Register::Pc as usize], HANDLER);
+        assert_eq!(vm.registers[Register::R6 as usize], 0x2EFE);
+        assert_eq!(vm.saved_usp, 0);
+        assert_eq!(vm.memory[0x2EFF], 0x2);
+        // RTI stays in supervisor mode.
+        vm.step();
+        vm.step();
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+        assert!(!vm.user_mode());
+        assert_eq!(vm.registers[Register::R6 as usize], 0x2F00);
+    }
+}
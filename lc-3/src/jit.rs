@@ -0,0 +1,720 @@
+// Dynamic binary translation of LC-3 code to x86-64.
+//
+// Straight-line code starting at PC is translated into a block of host code
+// that works on the VM register file and memory in place, the generated
+// function gets the register file in RDI and memory in RSI. A block ends at
+// the first branch, jump or subroutine call, or before the first instruction
+// it can't translate: traps, RTI, indirect loads and stores are left to the
+// interpreter so device registers and privileged state keep going through
+// `VirtualMachine`. Loads that hit a device register leave the block too.
+//
+// Condition codes are computed lazily. The result of the last instruction
+// setting them is kept in DX and only turned into N, Z and P bits when the
+// block exits or a conditional branch needs them.
+//
+// Blocks are cached by start address in an executable buffer, the cache is
+// flushed when the buffer fills up. Interrupts are taken between blocks, a
+// block branching back to its own start loops in host code for a bounded
+// number of iterations before returning.
+//
+// Programs may overwrite their own code. Pages blocks were translated from
+// are marked in `VirtualMachine::code_pages`, blocks on pages written to since
+// are dropped and translated again before they next run.
+use std::io;
+use std::ptr;
+
+use crate::devices::{Devices, KBSR};
+use crate::pages::PAGE_BITS;
+use crate::vm::{
+    dr, imm5, imm_mode, jsr_long, nzp, offset6, pc_offset11, pc_offset9, sr1,
+    sr2, OPCode, Register, RunResult, VirtualMachine, MEMORY_MAX,
+};
+
+// Size of the executable code buffer.
+const CODE_SIZE: usize = 1 << 20;
+// Longest block in LC-3 instructions.
+const MAX_BLOCK: usize = 64;
+// Iterations of a block looping on itself before it returns.
+const LOOP_BUDGET: u32 = 1024;
+
+// Offsets of the PC and condition registers in the register file.
+const PC: u8 = Register::Pc as u8 * 2;
+const COND: u8 = Register::Cond as u8 * 2;
+
+// Host registers used as scratch.
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+
+// Translated block, called with the register file and memory.
+type Block = unsafe extern "C" fn(registers: *mut u16, memory: *const u16);
+
+// Memory mapped with mmap, writable while code is copied in and executable
+// otherwise.
+struct CodeBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl CodeBuffer {
+    fn new() -> io::Result<Self> {
+        // SAFETY: anonymous private mapping, no file or address involved.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                CODE_SIZE,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len: 0,
+        })
+    }
+
+    fn protect(&self, prot: libc::c_int) {
+        // SAFETY: changes the protection of the whole mapping we own.
+        let ret = unsafe { libc::mprotect(self.ptr.cast(), CODE_SIZE, prot) };
+        assert_eq!(ret, 0, "mprotect failed: {}", io::Error::last_os_error());
+    }
+
+    // Copy code into the buffer, returns its offset or `None` if it's full.
+    fn push(&mut self, code: &[u8]) -> Option<usize> {
+        if self.len + code.len() > CODE_SIZE {
+            return None;
+        }
+        let offset = self.len;
+        self.protect(libc::PROT_READ | libc::PROT_WRITE);
+        // SAFETY: the range was checked to be inside the mapping, which is
+        // writable until protected again.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                code.as_ptr(),
+                self.ptr.add(offset),
+                code.len(),
+            );
+        }
+        self.protect(libc::PROT_READ | libc::PROT_EXEC);
+        self.len += code.len();
+        Some(offset)
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn block(&self, offset: usize) -> Block {
+        // SAFETY: offsets come from `push`, which copied a complete function
+        // there.
+        unsafe { std::mem::transmute::<*mut u8, Block>(self.ptr.add(offset)) }
+    }
+}
+
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+        // SAFETY: unmaps the mapping created in `new`.
+        unsafe {
+            libc::munmap(self.ptr.cast(), CODE_SIZE);
+        }
+    }
+}
+
+// x86-64 machine code emitter, limited to the instructions blocks need.
+#[derive(Default)]
+struct Emitter {
+    code: Vec<u8>,
+}
+
+impl Emitter {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn imm32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    // movzx host, word [rdi + register]
+    fn load_register(&mut self, host: u8, r: u16) {
+        self.bytes(&[0x0F, 0xB7, 0x47 | host << 3, r as u8 * 2]);
+    }
+
+    // mov word [rdi + offset], ax
+    fn store(&mut self, offset: u8) {
+        self.bytes(&[0x66, 0x89, 0x47, offset]);
+    }
+
+    fn store_register(&mut self, r: u16) {
+        self.store(r as u8 * 2);
+    }
+
+    // mov word [rdi + offset], value
+    fn store_imm(&mut self, offset: u8, value: u16) {
+        self.bytes(&[0x66, 0xC7, 0x47, offset]);
+        self.imm16(value);
+    }
+
+    // mov edx, eax, keeps the result for the condition codes.
+    fn set_result(&mut self) {
+        self.bytes(&[0x89, 0xC2]);
+    }
+
+    // Turn the result in DX into condition codes.
+    fn flags(&mut self) {
+        self.store_imm(COND, 0b010);
+        // test dx, dx; jz done
+        self.bytes(&[0x66, 0x85, 0xD2, 0x74, 14]);
+        self.store_imm(COND, 0b001);
+        // jns done
+        self.bytes(&[0x79, 6]);
+        self.store_imm(COND, 0b100);
+    }
+
+    // mov r8d, LOOP_BUDGET, returns the offset following it where loops
+    // branch back to.
+    fn prologue(&mut self) -> usize {
+        self.bytes(&[0x41, 0xB8]);
+        self.imm32(LOOP_BUDGET);
+        self.code.len()
+    }
+
+    // Branch back to `start` while the loop budget lasts, then leave the
+    // block continuing at `pc`.
+    fn loop_back(&mut self, start: usize, pc: u16) {
+        // dec r8d; jnz start
+        self.bytes(&[0x41, 0xFF, 0xC8, 0x0F, 0x85]);
+        let rel = start as i64 - (self.code.len() as i64 + 4);
+        self.imm32(rel as i32 as u32);
+        self.exit(pc);
+    }
+
+    // Leave the block continuing at `pc`.
+    fn exit(&mut self, pc: u16) {
+        self.store_imm(PC, pc);
+        self.bytes(&[0xC3]);
+    }
+
+    // Leave the block continuing at the address in AX.
+    fn exit_indirect(&mut self) {
+        self.store(PC);
+        self.bytes(&[0xC3]);
+    }
+}
+
+// Translate the block starting at `start`, returns its code and the address
+// of its last instruction, or `None` if its first instruction has to be
+// interpreted.
+fn translate(memory: &[u16; MEMORY_MAX], start: u16) -> Option<(Vec<u8>, u16)> {
+    let mut e = Emitter::default();
+    let loop_start = e.prologue();
+    // Whether DX holds a result whose condition codes are still pending.
+    let mut pending = false;
+    let mut pc = start;
+    for _ in 0..MAX_BLOCK {
+        if Devices::maps(pc) {
+            break;
+        }
+        let inst = memory[pc as usize];
+        let next = pc.wrapping_add(1);
+        let op = OPCode::get(inst >> 12).expect("opcode is four bits wide");
+        match op {
+            OPCode::Add | OPCode::And => {
+                e.load_register(EAX, sr1(inst));
+                if imm_mode(inst) {
+                    let opcode = if let OPCode::Add = op { 0x05 } else { 0x25 };
+                    e.bytes(&[opcode]);
+                    e.imm32(imm5(inst) as u32);
+                } else {
+                    e.load_register(ECX, sr2(inst));
+                    let opcode = if let OPCode::Add = op { 0x01 } else { 0x21 };
+                    e.bytes(&[opcode, 0xC8]);
+                }
+                e.store_register(dr(inst));
+                e.set_result();
+                pending = true;
+            }
+            OPCode::Not => {
+                e.load_register(EAX, sr1(inst));
+                e.bytes(&[0xF7, 0xD0]);
+                e.store_register(dr(inst));
+                e.set_result();
+                pending = true;
+            }
+            OPCode::Lea => {
+                let address = next.wrapping_add(pc_offset9(inst));
+                e.store_imm(dr(inst) as u8 * 2, address);
+                // mov edx, address
+                e.bytes(&[0xBA]);
+                e.imm32(address as u32);
+                pending = true;
+            }
+            OPCode::Ld => {
+                let address = next.wrapping_add(pc_offset9(inst));
+                if Devices::maps(address) {
+                    break;
+                }
+                // movzx eax, word [rsi + address * 2]
+                e.bytes(&[0x0F, 0xB7, 0x86]);
+                e.imm32(address as u32 * 2);
+                e.store_register(dr(inst));
+                e.set_result();
+                pending = true;
+            }
+            OPCode::Ldr => {
+                e.load_register(EAX, sr1(inst));
+                // add eax, offset; movzx eax, ax
+                e.bytes(&[0x05]);
+                e.imm32(offset6(inst) as u32);
+                e.bytes(&[0x0F, 0xB7, 0xC0]);
+                // Device registers are read by the interpreter, leave the
+                // block before this instruction.
+                let mut device = Emitter::default();
+                if pending {
+                    device.flags();
+                }
+                device.exit(pc);
+                // cmp eax, KBSR; jb load
+                e.bytes(&[0x3D]);
+                e.imm32(KBSR as u32);
+                e.bytes(&[0x72, device.code.len() as u8]);
+                e.bytes(&device.code);
+                // movzx eax, word [rsi + rax * 2]
+                e.bytes(&[0x0F, 0xB7, 0x04, 0x46]);
+                e.store_register(dr(inst));
+                e.set_result();
+                pending = true;
+            }
+            // BR without condition bits is a no-op.
+            OPCode::Br if nzp(inst) == 0 => (),
+            OPCode::Br => {
+                let target = next.wrapping_add(pc_offset9(inst));
+                if pending {
+                    e.flags();
+                }
+                if nzp(inst) != 0b111 {
+                    // test word [rdi + COND], nzp; jnz taken
+                    e.bytes(&[0x66, 0xF7, 0x47, COND]);
+                    e.imm16(nzp(inst));
+                    let mut not_taken = Emitter::default();
+                    not_taken.exit(next);
+                    e.bytes(&[0x75, not_taken.code.len() as u8]);
+                    e.bytes(&not_taken.code);
+                }
+                if target == start {
+                    e.loop_back(loop_start, target);
+                } else {
+                    e.exit(target);
+                }
+                return Some((e.code, pc));
+            }
+            OPCode::Jmp => {
+                if pending {
+                    e.flags();
+                }
+                e.load_register(EAX, sr1(inst));
+                e.exit_indirect();
+                return Some((e.code, pc));
+            }
+            OPCode::Jsr => {
+                if pending {
+                    e.flags();
+                }
+                if jsr_long(inst) {
+                    e.store_imm(Register::R7 as u8 * 2, next);
+                    e.exit(next.wrapping_add(pc_offset11(inst)));
+                } else {
+                    // Read the base register first, it may be R7.
+                    e.load_register(EAX, sr1(inst));
+                    e.store_imm(Register::R7 as u8 * 2, next);
+                    e.exit_indirect();
+                }
+                return Some((e.code, pc));
+            }
+            _ => break,
+        }
+        pc = next;
+        if pc == 0 {
+            break;
+        }
+    }
+    if pc == start {
+        return None;
+    }
+    if pending {
+        e.flags();
+    }
+    e.exit(pc);
+    Some((e.code, pc.wrapping_sub(1)))
+}
+
+// Block cache entry for an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Entry {
+    Untranslated,
+    // The instruction is run by the interpreter.
+    Interpreted,
+    // Offset of the translated block in the code buffer and address of its
+    // last instruction.
+    Translated(usize, u16),
+}
+
+// Translator and cache of translated blocks.
+pub struct Jit {
+    code: CodeBuffer,
+    // Cache entries indexed by start address.
+    blocks: Vec<Entry>,
+}
+
+impl Jit {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            code: CodeBuffer::new()?,
+            blocks: vec![Entry::Untranslated; MEMORY_MAX],
+        })
+    }
+
+    // Drop all translated blocks.
+    pub fn flush(&mut self) {
+        self.blocks.fill(Entry::Untranslated);
+        self.code.clear();
+    }
+
+    // Offset of the block starting at `pc`, translating it if needed.
+    fn block(&mut self, vm: &mut VirtualMachine, pc: u16) -> Option<usize> {
+        match self.blocks[pc as usize] {
+            Entry::Interpreted => return None,
+            Entry::Translated(offset, _) => return Some(offset),
+            Entry::Untranslated => (),
+        }
+        let Some((code, end)) = translate(&vm.memory, pc) else {
+            self.blocks[pc as usize] = Entry::Interpreted;
+            return None;
+        };
+        let offset = self.code.push(&code).unwrap_or_else(|| {
+            self.flush();
+            self.code
+                .push(&code)
+                .expect("block fits in an empty buffer")
+        });
+        vm.code_pages.mark(pc, end);
+        self.blocks[pc as usize] = Entry::Translated(offset, end);
+        Some(offset)
+    }
+
+    // Drop cache entries for code on pages that were written to, their
+    // blocks are translated again when they next run.
+    fn invalidate(&mut self, pages: &[usize]) {
+        for &page in pages {
+            let first = page << PAGE_BITS;
+            let last = first + (1 << PAGE_BITS) - 1;
+            // Blocks starting on earlier pages may extend into this one.
+            for start in first.saturating_sub(MAX_BLOCK)..=last {
+                match self.blocks[start] {
+                    Entry::Translated(_, end) if end as usize >= first => (),
+                    Entry::Interpreted if start >= first => (),
+                    _ => continue,
+                }
+                self.blocks[start] = Entry::Untranslated;
+            }
+        }
+    }
+
+    // Run until the machine halts, executing translated blocks where
+    // possible.
+    pub fn run(&mut self, vm: &mut VirtualMachine) -> RunResult {
+        while !vm.halted {
+            // Stores run in the interpreter, code they overwrote is dropped
+            // before the next block.
+            if vm.code_pages.dirty() {
+                let pages = vm.code_pages.take_written();
+                self.invalidate(&pages);
+            }
+            let pc = vm.registers[Register::Pc as usize];
+            let Some(offset) = self.block(vm, pc) else {
+                vm.step();
+                continue;
+            };
+            vm.check_interrupts();
+            if vm.registers[Register::Pc as usize] != pc {
+                continue;
+            }
+            let block = self.code.block(offset);
+            // SAFETY: blocks only access the register file and memory
+            // words at addresses below 65536.
+            unsafe { block(vm.registers.as_mut_ptr(), vm.memory.as_ptr()) };
+            // A block leaving before its first instruction hit a device
+            // register, the interpreter executes that instruction.
+            if vm.registers[Register::Pc as usize] == pc {
+                vm.step();
+            }
+        }
+        vm.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+
+    // A machine with `words` loaded at x3000, ready to run them.
+    fn machine(
+        words: &[u16],
+        input: &[u8],
+    ) -> (VirtualMachine, ScriptedConsole) {
+        let console = ScriptedConsole::new(input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        vm.memory[0x3000..0x3000 + words.len()].copy_from_slice(words);
+        vm.registers[Register::Pc as usize] = 0x3000;
+        vm.registers[Register::Cond as usize] = 0b010;
+        (vm, console)
+    }
+
+    // Run a program with the interpreter and with translated blocks, both
+    // must end in the same state with the same output. Returns the machine
+    // and the translator that ran it.
+    fn compare(
+        words: &[u16],
+        input: &[u8],
+        setup: impl Fn(&mut VirtualMachine),
+    ) -> (VirtualMachine, Jit) {
+        let (mut interpreted, interpreted_console) = machine(words, input);
+        let (mut translated, translated_console) = machine(words, input);
+        setup(&mut interpreted);
+        setup(&mut translated);
+        let expected = interpreted.run();
+        let mut jit = Jit::new().unwrap();
+        assert_eq!(jit.run(&mut translated), expected);
+        assert_eq!(translated.registers, interpreted.registers);
+        assert_eq!(translated.psr, interpreted.psr);
+        assert!(translated.memory == interpreted.memory);
+        assert_eq!(translated_console.output(), interpreted_console.output());
+        (translated, jit)
+    }
+
+    fn program(source: &str) -> Vec<u16> {
+        let program = assemble(source).unwrap();
+        assert_eq!(program.origin, 0x3000);
+        program.words
+    }
+
+    #[test]
+    fn loop_over_budget() {
+        // The loop body is a block branching back to its own start, it runs
+        // for several loop budgets.
+        let words = program(
+            ".ORIG x3000\n\
+             LD R1, COUNT\n\
+             LOOP ADD R2, R2, #3\n\
+             ADD R1, R1, #-1\n\
+             BRp LOOP\n\
+             HALT\n\
+             COUNT .FILL #5000\n\
+             .END",
+        );
+        const { assert!(5000 > LOOP_BUDGET * 4) };
+        let (vm, _) = compare(&words, b"", |_| ());
+        assert_eq!(vm.registers[2], 15000);
+        assert_eq!(vm.registers[Register::Cond as usize], 0b010);
+    }
+
+    #[test]
+    fn device_register_load() {
+        // LDR from KBSR and KBDR leaves the block for the interpreter, input
+        // running out halts the machine while polling.
+        let words = program(
+            ".ORIG x3000\n\
+             LD R2, KBSR\n\
+             POLL LDR R0, R2, #0\n\
+             BRzp POLL\n\
+             LDR R0, R2, #2\n\
+             ADD R1, R1, #1\n\
+             STR R0, R2, #6\n\
+             BRnzp POLL\n\
+             KBSR .FILL xFE00\n\
+             .END",
+        );
+        let (vm, _) = compare(&words, b"abc", |_| ());
+        assert_eq!(vm.registers[1], 3);
+    }
+
+    #[test]
+    fn subroutines() {
+        let words = program(
+            ".ORIG x3000\n\
+             LEA R0, MSG\n\
+             JSR LENGTH\n\
+             LEA R3, LENGTH\n\
+             JSRR R3\n\
+             NOT R4, R1\n\
+             AND R5, R4, #-16\n\
+             ST R1, SAVED\n\
+             PUTS\n\
+             HALT\n\
+             LENGTH AND R1, R1, #0\n\
+             ADD R2, R0, #0\n\
+             NEXT LDR R3, R2, #0\n\
+             BRz DONE\n\
+             ADD R1, R1, #1\n\
+             ADD R2, R2, #1\n\
+             BRnzp NEXT\n\
+             DONE RET\n\
+             SAVED .BLKW 1\n\
+             MSG .STRINGZ \"translated\"\n\
+             .END",
+        );
+        let (vm, _) = compare(&words, b"", |_| ());
+        assert_eq!(vm.registers[1], 10);
+    }
+
+    // xorshift generator, deterministic so failures can be reproduced.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u16 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 32) as u16
+        }
+
+        fn below(&mut self, n: u16) -> u16 {
+            self.next() % n
+        }
+    }
+
+    // Straight-line code with forward branches ending in HALT. Loads and
+    // stores address a data area after the code through R6 or PC relative
+    // offsets, so programs can't overwrite themselves and always halt.
+    fn random_program(random: &mut Random) -> Vec<u16> {
+        let len = 1 + random.below(60);
+        let data = len + 1;
+        let mut words = vec![];
+        for pc in 0..len {
+            // Destination registers other than R6.
+            let dr = [0, 1, 2, 3, 4, 5, 7][random.below(7) as usize] << 9;
+            let sr = random.below(8) << 6;
+            let to_data = data + random.below(32) - (pc + 1);
+            let inst = match random.below(11) {
+                0 => 0x1000 | dr | sr | random.below(8),
+                1 => 0x1020 | dr | sr | random.below(32),
+                2 => 0x5000 | dr | sr | random.below(8),
+                3 => 0x5020 | dr | sr | random.below(32),
+                4 => 0x903f | dr | sr,
+                5 => 0xe000 | dr | (random.next() & 0x1ff),
+                6 => 0x2000 | dr | to_data,
+                7 => 0x6000 | dr | (6 << 6) | random.below(32),
+                8 => 0x3000 | dr | to_data,
+                9 => 0x7000 | dr | (6 << 6) | random.below(32),
+                _ => (random.below(8) << 9) | random.below(len - pc),
+            };
+            words.push(inst);
+        }
+        // HALT
+        words.push(0xf025);
+        words.extend((0..32).map(|_| random.next()));
+        words
+    }
+
+    #[test]
+    fn random_programs() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let words = random_program(&mut random);
+            let registers: Vec<u16> = (0..8).map(|_| random.next()).collect();
+            let data = 0x3000 + words.len() as u16 - 32;
+            compare(&words, b"", |vm| {
+                vm.registers[..8].copy_from_slice(&registers);
+                vm.registers[6] = data;
+            });
+        }
+    }
+
+    #[test]
+    fn patched_instruction_on_same_page() {
+        // The second pass runs the loop with its ADD replaced.
+        let words = program(
+            ".ORIG x3000\n\
+             AND R1, R1, #0\n\
+             ADD R1, R1, #2\n\
+             PASS AND R3, R3, #0\n\
+             ADD R3, R3, #4\n\
+             LOOP ADD R2, R2, #1\n\
+             ADD R3, R3, #-1\n\
+             BRp LOOP\n\
+             LD R4, PATCH\n\
+             ST R4, LOOP\n\
+             ADD R1, R1, #-1\n\
+             BRp PASS\n\
+             HALT\n\
+             PATCH ADD R2, R2, #5\n\
+             .END",
+        );
+        let (vm, _) = compare(&words, b"", |_| ());
+        assert_eq!(vm.registers[2], 4 + 4 * 5);
+    }
+
+    #[test]
+    fn patched_instruction_in_block_from_previous_page() {
+        // The block at START begins on page x30 and runs into page x31,
+        // where TARGET is patched.
+        let words = program(
+            ".ORIG x3000\n\
+             AND R1, R1, #0\n\
+             ADD R1, R1, #2\n\
+             BRnzp START\n\
+             .BLKW #249\n\
+             START ADD R2, R2, #1\n\
+             ADD R2, R2, #1\n\
+             ADD R2, R2, #1\n\
+             ADD R2, R2, #1\n\
+             TARGET ADD R2, R2, #1\n\
+             ADD R1, R1, #-1\n\
+             BRz DONE\n\
+             LD R4, PATCH\n\
+             ST R4, TARGET\n\
+             BRnzp START\n\
+             DONE HALT\n\
+             PATCH ADD R2, R2, #10\n\
+             .END",
+        );
+        assert_eq!(words[0xfc], 0x14a1);
+        assert_eq!(words[0x100], 0x14a1);
+        let (vm, _) = compare(&words, b"", |_| ());
+        assert_eq!(vm.registers[2], 5 + 4 + 10);
+    }
+
+    #[test]
+    fn interpreted_entry_translated_after_write() {
+        // SPOT starts with a store, which is interpreted, until it's
+        // replaced by an ADD that can be translated.
+        let words = program(
+            ".ORIG x3000\n\
+             AND R1, R1, #0\n\
+             ADD R1, R1, #2\n\
+             SPOT ST R0, SCRATCH\n\
+             ADD R1, R1, #-1\n\
+             BRz DONE\n\
+             LD R4, PATCH\n\
+             ST R4, SPOT\n\
+             BRnzp SPOT\n\
+             DONE HALT\n\
+             PATCH ADD R2, R2, #7\n\
+             SCRATCH .BLKW 1\n\
+             .END",
+        );
+        let (vm, jit) = compare(&words, b"", |_| ());
+        assert_eq!(vm.registers[2], 7);
+        assert!(matches!(jit.blocks[0x3002], Entry::Translated(_, _)));
+    }
+}
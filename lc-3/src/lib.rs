@@ -0,0 +1,22 @@
+pub mod asm;
+pub mod console;
+pub mod debug;
+pub mod decode;
+pub mod devices;
+pub mod disasm;
+pub mod gdb;
+pub mod grade;
+pub mod history;
+pub mod interrupts;
+#[cfg(all(target_arch = "x86_64", unix))]
+pub mod jit;
+pub mod lines;
+pub mod loader;
+pub mod os;
+pub mod pages;
+pub mod snapshot;
+pub mod symbols;
+#[cfg(unix)]
+pub mod terminal;
+pub mod trace;
+pub mod vm;
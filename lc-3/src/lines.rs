@@ -0,0 +1,67 @@
+// Line tables mapping instruction addresses to the source lines they were
+// assembled from, stored next to object files as `.dbg` files:
+//
+//     source prog.asm
+//     x3000 3
+//     x3001 4
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    // Path of the source file.
+    pub source: String,
+    lines: BTreeMap<u16, usize>,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Record the source line of the instruction at an address.
+    pub fn insert(&mut self, address: u16, line: usize) {
+        self.lines.insert(address, line);
+    }
+
+    // Source line of the instruction at an address.
+    pub fn line(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    // Addresses and their source lines, by address.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines.iter().map(|(&address, &line)| (address, line))
+    }
+
+    // Read a table in the `.dbg` file format, malformed lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            if let Some(source) = line.strip_prefix("source ") {
+                table.source = source.to_string();
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            if let (Some(address), Some(number), None) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                let address = address
+                    .strip_prefix('x')
+                    .and_then(|a| u16::from_str_radix(a, 16).ok());
+                if let (Some(address), Ok(number)) = (address, number.parse()) {
+                    table.insert(address, number);
+                }
+            }
+        }
+        table
+    }
+
+    // Render the table in the `.dbg` file format.
+    pub fn to_dbg(&self) -> String {
+        let mut out = format!("source {}\n", self.source);
+        for (address, line) in self.iter() {
+            out.push_str(&format!("x{:04X} {}\n", address, line));
+        }
+        out
+    }
+}
@@ -0,0 +1,233 @@
+// Loading LC-3 object files into memory.
+//
+// An object file is a sequence of big-endian words, the first word is the
+// origin where the remaining words get loaded.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::vm::{Register, VirtualMachine, MEMORY_MAX};
+
+// Range of memory covered by a loaded image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub origin: u16,
+    pub len: usize,
+}
+
+impl Segment {
+    // Address one past the last word of the segment.
+    pub fn end(&self) -> usize {
+        self.origin as usize + self.len
+    }
+
+    fn overlaps(&self, other: &Segment) -> bool {
+        (self.origin as usize) < other.end()
+            && (other.origin as usize) < self.end()
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // The file couldn't be read.
+    Io(String, io::Error),
+    // The file is empty or ends in the middle of a word.
+    Malformed(String),
+    // The image runs past the end of memory.
+    TooLarge(Segment),
+    // Two images write to the same memory.
+    Overlap(Segment, Segment),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(name, err) => write!(f, "{} : {}", name, err),
+            LoadError::Malformed(name) => {
+                write!(f, "{} : not an LC-3 object file", name)
+            }
+            LoadError::TooLarge(seg) => write!(
+                f,
+                "{} : image at x{:04X} is {} words long and runs past the \
+                 end of memory",
+                seg.name, seg.origin, seg.len
+            ),
+            LoadError::Overlap(a, b) => write!(
+                f,
+                "{} : image at x{:04X}-x{:04X} overlaps {} at x{:04X}-x{:04X}",
+                b.name,
+                b.origin,
+                b.end() - 1,
+                a.name,
+                a.origin,
+                a.end() - 1
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+// Split an object file into its origin and words.
+pub fn parse(name: &str, bytes: &[u8]) -> Result<(u16, Vec<u16>), LoadError> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return Err(LoadError::Malformed(name.to_string()));
+    }
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    let origin = words.next().unwrap();
+    Ok((origin, words.collect()))
+}
+
+// Parse an object file and find the memory it covers.
+fn place(name: &str, bytes: &[u8]) -> Result<(Segment, Vec<u16>), LoadError> {
+    let (origin, words) = parse(name, bytes)?;
+    let segment = Segment {
+        name: name.to_string(),
+        origin,
+        len: words.len(),
+    };
+    if segment.end() > MEMORY_MAX {
+        return Err(LoadError::TooLarge(segment));
+    }
+    Ok((segment, words))
+}
+
+impl VirtualMachine {
+    // Copy an object image into memory at its origin.
+    pub fn load_image(
+        &mut self,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<Segment, LoadError> {
+        let (segment, words) = place(name, bytes)?;
+        self.copy_image(&segment, &words);
+        Ok(segment)
+    }
+
+    // Images are copied straight to memory, bypassing device registers.
+    fn copy_image(&mut self, segment: &Segment, words: &[u16]) {
+        for address in segment.origin as usize..segment.end() {
+            self.memory_written(address as u16);
+        }
+        self.memory[segment.origin as usize..segment.end()]
+            .copy_from_slice(words);
+    }
+
+    // Load object files in order and point PC at the origin of the first
+    // one. Images must not overlap each other or the images already in
+    // `loaded`, such as the OS. Memory is left untouched if any file can't
+    // be loaded.
+    pub fn load_files(
+        &mut self,
+        paths: &[String],
+        loaded: &[Segment],
+    ) -> Result<Vec<Segment>, LoadError> {
+        let mut images: Vec<(Segment, Vec<u16>)> = vec![];
+        for path in paths {
+            let bytes = fs::read(path)
+                .map_err(|err| LoadError::Io(path.clone(), err))?;
+            let (segment, words) = place(path, &bytes)?;
+            if let Some(other) = loaded
+                .iter()
+                .chain(images.iter().map(|(s, _)| s))
+                .find(|s| s.overlaps(&segment))
+            {
+                return Err(LoadError::Overlap(other.clone(), segment));
+            }
+            images.push((segment, words));
+        }
+        for (segment, words) in &images {
+            self.copy_image(segment, words);
+        }
+        if let Some((first, _)) = images.first() {
+            self.registers[Register::Pc as usize] = first.origin;
+        }
+        Ok(images.into_iter().map(|(segment, _)| segment).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Write an object file to a fresh temporary path.
+    fn object(name: &str, origin: u16, words: &[u16]) -> String {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "lc3-loader-{}-{}.obj",
+            std::process::id(),
+            name
+        ));
+        let bytes: Vec<u8> = std::iter::once(origin)
+            .chain(words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn loads_files_in_order() {
+        let a = object("order-a", 0x3000, &[1, 2]);
+        let b = object("order-b", 0x4000, &[3]);
+        let mut vm = VirtualMachine::new();
+        let segments = vm.load_files(&[a, b], &[]).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(&vm.memory[0x3000..0x3002], &[1, 2]);
+        assert_eq!(vm.memory[0x4000], 3);
+        assert_eq!(vm.registers[Register::Pc as usize], 0x3000);
+    }
+
+    #[test]
+    fn overlap_leaves_memory_untouched() {
+        let a = object("overlap-a", 0x3000, &[1, 2, 3]);
+        let b = object("overlap-b", 0x3002, &[4]);
+        let mut vm = VirtualMachine::new();
+        let err = vm.load_files(&[a.clone(), b.clone()], &[]).unwrap_err();
+        let LoadError::Overlap(first, second) = err else {
+            panic!("expected an overlap, got {}", err);
+        };
+        assert_eq!((first.name, second.name), (a, b));
+        assert!(vm.memory[0x3000..0x3003].iter().all(|&w| w == 0));
+        assert_eq!(vm.registers[Register::Pc as usize], 0);
+    }
+
+    #[test]
+    fn images_may_not_overwrite_the_os() {
+        let program = object("os", 0x0020, &[0x3000]);
+        let mut vm = VirtualMachine::new();
+        let os = vm.load_os();
+        let vector = vm.memory[0x20];
+        let err = vm.load_files(&[program], &[os]).unwrap_err();
+        assert!(
+            matches!(err, LoadError::Overlap(ref s, _) if s.name == "lc3os")
+        );
+        assert_eq!(vm.memory[0x20], vector);
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let mut vm = VirtualMachine::new();
+        assert!(matches!(
+            vm.load_image("odd", &[0x30, 0x00, 0x01]),
+            Err(LoadError::Malformed(_))
+        ));
+        assert!(matches!(
+            vm.load_image("empty", &[]),
+            Err(LoadError::Malformed(_))
+        ));
+        assert!(matches!(
+            vm.load_image("large", &[0xff, 0xff, 0, 1, 0, 2]),
+            Err(LoadError::TooLarge(_))
+        ));
+        let missing = "/nonexistent/lc3-loader.obj".to_string();
+        assert!(matches!(
+            vm.load_files(&[missing], &[]),
+            Err(LoadError::Io(..))
+        ));
+    }
+}
@@ -1,343 +1,395 @@
-// Memory for LC-3 VM, has max size 65536 cells.
-const MEMORY_MAX: usize = 1 << 16;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 
-// Register definitions.
-#[derive(Copy, Clone, Debug)]
-enum Register {
-    R0,
-    R1,
-    R2,
-    R3,
-    R4,
-    R5,
-    R6,
-    R7,
-    Pc,
-    Cond,
-    Count,
-}
+use lc_3::asm;
+use lc_3::console::{Console, StdConsole};
+use lc_3::debug::Debugger;
+use lc_3::gdb::{self, End};
+use lc_3::grade::{self, Spec};
+#[cfg(all(target_arch = "x86_64", unix))]
+use lc_3::jit::Jit;
+use lc_3::lines::LineTable;
+use lc_3::os::OsMode;
+use lc_3::symbols::SymbolTable;
+#[cfg(unix)]
+use lc_3::terminal::TerminalConsole;
+use lc_3::trace::{self, Coverage};
+use lc_3::vm::{CondFlags, Register, RunResult, VirtualMachine};
 
-// Opcodes
-#[derive(Copy, Clone, Debug)]
-enum OPCode {
-    Br,
-    Add,
-    Ld,
-    St,
-    Jsr,
-    And,
-    Ldr,
-    Str,
-    Rti,
-    Not,
-    Ldi,
-    Sti,
-    Jmp,
-    Res,
-    Lea,
-    Trap,
-}
+const USAGE_CMD: &str = "LC-3 virtual machine.\n
+Usage: lc-3 run [--os native|image] [--gdb address:port] [--jit] [--trace] [--coverage file.lcov] [file.obj] [more.obj...] -- Loads object files and runs the first one.
+    --os native : traps are serviced by the VM (default).
+    --os image : traps are serviced by the bundled LC-3 operating system image.
+    --gdb [address:port] : waits for GDB to connect and runs the program under its control.
+    --jit : translates the program to x86-64 code as it runs.
+    --trace : logs every instruction with the registers it changed to standard error.
+    --coverage [file.lcov] : writes how often each source line was executed, lines are read from the .dbg files.
+Usage: lc-3 debug [--os native|image] [file.obj] [more.obj...] -- Loads object files and debugs the first one, type help for commands.
+Usage: lc-3 resume [--debug] [file.snap] -- Restores a snapshot saved by the debugger and runs it.
+    --debug : debugs the restored machine instead of running it.
+Usage: lc-3 test [spec.toml] [--junit report.xml] -- Runs the test cases of a spec and checks the registers, memory and output.
+    --junit [report.xml] : writes the results as a JUnit XML report.
+Usage: lc-3 asm [file.asm] [-o file.obj] -- Assembles a program to an object file, a symbol table and a line table.
+Usage: lc-3 disasm [file.obj] [file.sym] -- Disassembles an object file, labels are read from the symbol table.
+";
 
-impl OPCode {
-    // We can't cast enums to integers (for good reason) this function
-    // allows us to "cast" u16 values in memory as opcodes if they're
-    // a match.
-    pub fn get(op: u16) -> Option<OPCode> {
-        match op {
-            0 => Some(OPCode::Br),
-            1 => Some(OPCode::Add),
-            2 => Some(OPCode::Ld),
-            3 => Some(OPCode::St),
-            4 => Some(OPCode::Jsr),
-            5 => Some(OPCode::And),
-            6 => Some(OPCode::Ldr),
-            7 => Some(OPCode::Str),
-            8 => Some(OPCode::Rti),
-            9 => Some(OPCode::Not),
-            10 => Some(OPCode::Ldi),
-            11 => Some(OPCode::Sti),
-            12 => Some(OPCode::Jmp),
-            13 => Some(OPCode::Res),
-            14 => Some(OPCode::Lea),
-            15 => Some(OPCode::Trap),
-            _ => None,
+// Assemble a program, the symbol table is written next to the object file.
+fn assemble(args: &[String]) {
+    let mut input = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().cloned(),
+            _ => input = Some(arg),
+        }
+    }
+    let Some(input) = input else {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
+    };
+    let source = fs::read_to_string(input).unwrap_or_else(|err| {
+        eprintln!("{} : {}", input, err);
+        process::exit(1);
+    });
+    let assembly = asm::assemble(&source).unwrap_or_else(|errors| {
+        for err in errors {
+            eprintln!("{}:{}: {}", input, err.line, err.message);
         }
+        process::exit(1);
+    });
+    let output = output.unwrap_or_else(|| {
+        Path::new(input)
+            .with_extension("obj")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let symbols = Path::new(&output).with_extension("sym");
+    let lines = Path::new(&output).with_extension("dbg");
+    let mut assembly = assembly;
+    assembly.lines.source = input.clone();
+    if let Err(err) = fs::write(&output, assembly.to_obj())
+        .and_then(|_| fs::write(&symbols, assembly.symbols.to_sym()))
+        .and_then(|_| fs::write(&lines, assembly.lines.to_dbg()))
+    {
+        eprintln!("{} : {}", output, err);
+        process::exit(1);
     }
 }
 
-// Condition flags.
-#[derive(Copy, Clone, Debug)]
-enum CondFlags {
-    Pos = 1 << 0,
-    Zero = 1 << 1,
-    Neg = 1 << 2,
+// Disassemble an object file. The symbol table defaults to the `.sym` file
+// next to the object file when there is one.
+fn disassemble(args: &[String]) {
+    let Some(input) = args.first() else {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
+    };
+    let symbols = args
+        .get(1)
+        .cloned()
+        .or_else(|| {
+            let path = Path::new(input).with_extension("sym");
+            path.exists().then(|| path.to_string_lossy().into_owned())
+        })
+        .map(|path| {
+            fs::read_to_string(&path).unwrap_or_else(|err| {
+                eprintln!("{} : {}", path, err);
+                process::exit(1);
+            })
+        })
+        .map(|text| SymbolTable::parse(&text));
+
+    let mut vm = VirtualMachine::new();
+    let bytes = fs::read(input).unwrap_or_else(|err| {
+        eprintln!("{} : {}", input, err);
+        process::exit(1);
+    });
+    let segment = vm.load_image(input, &bytes).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    for line in vm.disassemble_with_symbols(
+        segment.origin,
+        segment.len,
+        symbols.as_ref(),
+    ) {
+        println!("{}", line);
+    }
 }
 
-// Virtual machine
-#[derive(Debug)]
-pub struct VirtualMachine {
-    pub registers: [u16; Register::Count as usize],
-    pub memory: [u16; MEMORY_MAX],
+// Console for running programs, interactive terminals are switched to raw
+// mode while piped input is read as is.
+fn console() -> Box<dyn Console> {
+    #[cfg(unix)]
+    if let Some(terminal) = TerminalConsole::new() {
+        return Box::new(terminal);
+    }
+    Box::<StdConsole>::default()
 }
 
-impl VirtualMachine {
-    // Create a new VM instance.
-    pub fn new() -> Self {
-        Self {
-            registers: [0 as u16; Register::Count as usize],
-            memory: [0 as u16; MEMORY_MAX],
+// Parse the `--os` option and the object files of the commands running
+// programs.
+fn program_args(args: &[String]) -> (OsMode, Vec<String>) {
+    let mut os = OsMode::Native;
+    let mut files = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--os" => {
+                os = match args.next().map(String::as_str) {
+                    Some("native") => OsMode::Native,
+                    Some("image") => OsMode::Image,
+                    _ => {
+                        println!("{}", USAGE_CMD);
+                        process::exit(1);
+                    }
+                }
+            }
+            _ => files.push(arg.clone()),
         }
     }
-    // Read from memory at given index.
-    pub fn read(&self, pos: usize) -> u16 {
-        self.memory[pos]
+    if files.is_empty() {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
     }
+    (os, files)
+}
 
-    // Read from memory at address.
-    pub fn mem_read(&self, address: usize) -> u16 {
-        self.memory[address]
+// Create a VM with the object files loaded, ready to run the first one.
+fn boot(os: OsMode, files: &[String]) -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    // Start by setting the Z flag.
+    vm.registers[Register::Cond as usize] = CondFlags::Zero as u16;
+    let mut loaded = vec![];
+    if os == OsMode::Image {
+        loaded.push(vm.load_os());
     }
-
-    // Write at memory address.
-    pub fn mem_write(&mut self, address: usize, value: u16) {
-        self.memory[address] = value
-    }
-
-    // Update condition flags on each register write.
-    pub fn update_flags(&mut self, r: u16) {
-        if self.registers[r as usize] == 0 {
-            self.registers[Register::Cond as usize] = CondFlags::Zero as u16;
-        } else if (self.registers[r as usize] >> 15) != 0 {
-            self.registers[Register::Cond as usize] = CondFlags::Neg as u16;
-        } else {
-            self.registers[Register::Cond as usize] = CondFlags::Pos as u16;
-        }
+    if let Err(err) = vm.load_files(files, &loaded) {
+        eprintln!("{}", err);
+        process::exit(1);
     }
+    vm
+}
 
-    // Execute add instruction with support for immediate mode flag.
-    pub fn add(&mut self, inst: u16) {
-        // destination register.
-        let r0 = (inst >> 9) & 0x7;
-        // source operand.
-        let r1 = (inst >> 6) & 0x7;
-        // execution mode (immediate or register)
-        let mode = (inst >> 5) & 0x1;
-        // TODO: make it a match
-        if mode != 0 {
-            // five-bit immediate
-            let imm5 = sign_extend(inst & 0x1f, 5);
-            self.registers[r0 as usize] = self.registers[r1 as usize] + imm5;
-        } else {
-            let r2 = inst & 0x7;
-            self.registers[r0 as usize] =
-                self.registers[r1 as usize] + self.registers[r2 as usize];
+// Run translating LC-3 code to host code.
+#[cfg(all(target_arch = "x86_64", unix))]
+fn run_jit(vm: &mut VirtualMachine) -> RunResult {
+    match Jit::new() {
+        Ok(mut jit) => jit.run(vm),
+        Err(err) => {
+            eprintln!("Failed to allocate code memory : {}", err);
+            process::exit(1);
         }
-        self.update_flags(r0)
     }
+}
 
-    // Execute Ldi instruction, load a value from a PC relative offset into
-    // the destination register.
-    // Encoding format :
-    // opcode (4 bits) | destination register (3 bits) | offset (9 bits)
-    // PC is incremented before we compute the relative offset in the execution
-    // loop.
-    pub fn ldi(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let offset = sign_extend(inst & 0x1FF, 9);
-        let rel_offset = self.registers[Register::Pc as usize] + offset;
-        let size = 2 as usize;
-        self.registers[r0 as usize] = self.mem_read(rel_offset as usize);
-        self.update_flags(r0)
-    }
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+fn run_jit(_vm: &mut VirtualMachine) -> RunResult {
+    eprintln!("--jit is only supported on x86-64");
+    process::exit(1);
+}
 
-    // Bitwise AND.
-    pub fn and(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let r1 = (inst >> 6) & 0x7;
-        let imm_flag = (inst >> 5) & 0x1;
+// Line tables from the `.dbg` files next to object files.
+fn line_tables(files: &[String]) -> Vec<LineTable> {
+    files
+        .iter()
+        .filter_map(|file| {
+            let path = Path::new(file).with_extension("dbg");
+            fs::read_to_string(path).ok()
+        })
+        .map(|text| LineTable::parse(&text))
+        .collect()
+}
 
-        if imm_flag != 0 {
-            let imm5 = sign_extend(inst & 0x1f, 5);
-            self.registers[r0 as usize] = self.registers[r1 as usize] & imm5;
-        } else {
-            let r2 = inst & 0x7;
-            self.registers[r0 as usize] =
-                self.registers[r1 as usize] & self.registers[r2 as usize];
+// Load object files and run until the machine halts.
+fn run(args: &[String]) {
+    let mut gdb = None;
+    let mut jit = false;
+    let mut trace = false;
+    let mut coverage = None;
+    let mut program = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gdb" => gdb = args.next().cloned(),
+            "--jit" => jit = true,
+            "--trace" => trace = true,
+            "--coverage" => coverage = args.next().cloned(),
+            _ => program.push(arg.clone()),
         }
-        self.update_flags(r0)
     }
-
-    // Bitwise NOT.
-    pub fn not(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let r1 = (inst >> 6) & 0x7;
-
-        self.registers[r0 as usize] = !self.registers[r1 as usize];
-        self.update_flags(r0)
+    let (os, files) = program_args(&program);
+    let traced = trace || coverage.is_some();
+    if jit && traced {
+        eprintln!("--trace and --coverage can't be combined with --jit");
+        process::exit(1);
     }
-
-    // Branch
-    pub fn br(&mut self, inst: u16) {
-        let offset = sign_extend(inst & 0x1ff, 9);
-        let cond_flag = (inst >> 9) & 0x7;
-        let cond = self.registers[Register::Cond as usize];
-
-        if (cond_flag & cond) != 0 {
-            self.registers[Register::Pc as usize] += offset;
+    let mut vm = boot(os, &files);
+    vm.devices.console = console();
+    if let Some(address) = gdb {
+        let mut debugger = Debugger::new(vm, SymbolTable::new());
+        let end = gdb::serve(&mut debugger, &address);
+        vm = debugger.vm;
+        match end {
+            Ok(End::Killed) => return,
+            Ok(_) => (),
+            Err(err) => {
+                drop(vm);
+                eprintln!("{} : {}", address, err);
+                process::exit(1);
+            }
         }
     }
-
-    // Jump
-    pub fn jmp(&mut self, inst: u16) {
-        let r1 = (inst >> 6) & 0x7;
-        self.registers[Register::Pc as usize] = self.registers[r1 as usize];
-    }
-
-    // Jump to register.
-    pub fn jsr(&mut self, inst: u16) {
-        let long_flag = (inst >> 11) & 1;
-        self.registers[Register::R7 as usize] =
-            self.registers[Register::Pc as usize];
-        if long_flag != 0 {
-            let offset = sign_extend(inst & 0x7ff, 11);
-            self.registers[Register::Pc as usize] += offset;
-        } else {
-            let r1 = (inst >> 6) & 0x7;
-            self.registers[Register::Pc as usize] = self.registers[r1 as usize];
+    let mut counts = coverage.as_ref().map(|_| Coverage::new());
+    let result = if jit {
+        Ok(run_jit(&mut vm))
+    } else if traced {
+        let symbols = SymbolTable::for_objects(&files);
+        let mut stderr = io::stderr();
+        let log = trace.then_some(&mut stderr as &mut dyn Write);
+        trace::run(&mut vm, Some(&symbols), log, counts.as_mut())
+    } else {
+        Ok(vm.run())
+    };
+    let pc = vm.registers[Register::Pc as usize];
+    // Restore the terminal before reporting errors.
+    drop(vm);
+    let result = result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    if let (Some(path), Some(counts)) = (coverage, counts) {
+        if let Err(err) = fs::write(&path, counts.to_lcov(&line_tables(&files)))
+        {
+            eprintln!("{} : {}", path, err);
+            process::exit(1);
         }
     }
-
-    // Load
-    pub fn ld(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let offset = sign_extend(inst & 0x1FF, 9);
-        self.registers[r0 as usize] =
-            self.mem_read((self.registers[Register::Pc as usize] + offset) as usize);
-        self.update_flags(r0)
-    }
-
-    // Load register
-    pub fn ldr(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let r1 = (inst >> 6) & 0x7;
-        let offset = sign_extend(inst & 0x3f, 6);
-        self.registers[r0 as usize] =
-            self.mem_read((self.registers[r1 as usize] + offset) as usize);
-        self.update_flags(r0)
+    if let RunResult::Unhandled(exception) = result {
+        eprintln!("Unhandled exception {:?} before x{:04X}", exception, pc);
+        process::exit(1);
     }
+}
 
-    // Load effective address
-    pub fn lea(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let offset = sign_extend(inst & 0x1ff, 9);
-        self.registers[r0 as usize] = self.registers[Register::Pc as usize] + offset;
-        self.update_flags(r0)
+// Load object files and debug the first one. Labels are read from the `.sym`
+// files next to the object files. The terminal stays in line mode so
+// commands can be edited, programs see their input a line at a time.
+fn debug(args: &[String]) {
+    let (os, files) = program_args(args);
+    let vm = boot(os, &files);
+    let symbols = SymbolTable::for_objects(&files);
+    if let Err(err) = Debugger::new(vm, symbols).repl() {
+        eprintln!("{}", err);
+        process::exit(1);
     }
+}
 
-    // Store.
-    pub fn st(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let offset = sign_extend(inst & 0x1ff, 9);
-        let addr = (self.registers[Register::Pc as usize] + offset) as usize;
-        self.mem_write(addr, self.registers[r0 as usize])
+// Restore a snapshot and run or debug it.
+fn resume(args: &[String]) {
+    let mut debug = false;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            _ => path = Some(arg),
+        }
     }
-
-    // Store indirect.
-    pub fn sti(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let offset = sign_extend(inst & 0x1ff, 9);
-        let addr = self.mem_read((self.registers[Register::Pc as usize] + offset) as usize);
-        self.mem_write(addr as usize, self.registers[r0 as usize])
+    let Some(path) = path else {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
+    };
+    let mut vm = VirtualMachine::new();
+    if let Err(err) = fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| vm.restore(&bytes).map_err(|err| err.to_string()))
+    {
+        eprintln!("{} : {}", path, err);
+        process::exit(1);
     }
-
-    // Store register.
-    pub fn str(&mut self, inst: u16) {
-        let r0 = (inst >> 9) & 0x7;
-        let r1 = (inst >> 6) & 0x7;
-        let offset = sign_extend(inst & 0x3f, 6);
-        self.mem_write(
-            (self.registers[r1 as usize] + offset) as usize,
-            self.registers[r0 as usize],
-        )
+    if debug {
+        if let Err(err) = Debugger::new(vm, SymbolTable::new()).repl() {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
     }
-
-    // Trap routines are implemented by passing execution to the host language
-    // runtime, in this case Rust. If for example a trap instruction was for
-    // suspending execution it would suspend it and pass it to a Debugger
-    // runtime function where you can step in or out. Same goes for handling
-    // I/O like writing to a terminal or reading from the keyboard.
-    pub fn trap(&self) {
-        todo!();
+    vm.devices.console = console();
+    let result = vm.run();
+    let pc = vm.registers[Register::Pc as usize];
+    // Restore the terminal before reporting errors.
+    drop(vm);
+    if let RunResult::Unhandled(exception) = result {
+        eprintln!("Unhandled exception {:?} before x{:04X}", exception, pc);
+        process::exit(1);
     }
 }
 
-// Sign extension for immediate values, sign extension is used
-// to extend values stored in n-bits to m-bits (m > n) and also
-// preserve their sign.
-fn sign_extend(x: u16, bit_count: usize) -> u16 {
-    // To explain this section an example would be better.
-    // First negative numbers are usually encoded using two complements
-    // The steps for two complements are very simple :
-    // Let's say we want the representation of -1.
-    // 1. Start with 1 in binary 5 bits 0b00001
-    // 2. Flip all the bits : 0b11110
-    // 3. Add 1 : 0b11111
-    // Sign extension works as follow we consider the above example extended
-    // to 16 bits.
-    // 1. Shift the bits of x to the left by their size in bits - 1
-    //    0b11111 >> (5 - 1) => 0b1
-    // 2. Do bitwise AND with 1 => 0b1 AND 1 =>1 != 0
-    // 3. Do bitwise OR with the maximum value of the target in this case
-    //    16 bits <=> 0xFFFF and then right shift by the original bit size.
-    // 4. 0b11111 | 0b1111111111111111 <=> 0b1111111111111111<< 5
-    //    which gives us : 0b1111111100000
-    if ((x >> (bit_count - 1)) & 1) != 0 {
-        x | (0xFFFF << bit_count)
-    } else {
-        x
+// Run the test cases of a spec, exits with an error if any of them failed.
+fn test(args: &[String]) {
+    let mut spec = None;
+    let mut junit = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => junit = args.next().cloned(),
+            _ => spec = Some(arg),
+        }
     }
-}
-
-fn main() {
-    // Program counter starts at 0x3000.
-    let PcStart = 0x3000 as u16;
-    // Start by setting the Z flag.
-    let mut vm = VirtualMachine::new();
-    vm.registers[Register::Cond as usize] = CondFlags::Zero as u16;
-    vm.registers[Register::Pc as usize] = 0x3000;
-    vm.memory[0x3000] = OPCode::Add as u16;
-    loop {
-        // Offset of the next instruction.
-        let offset = vm.registers[Register::Pc as usize];
-        // Increment the program counter.
-        vm.registers[Register::Pc as usize] += 1;
-        // Fetch the next instruction.
-        let inst = vm.read(offset as usize);
-        // Decode the next instruction.
-        let op = inst >> 12;
-
-        match OPCode::get(inst) {
-            Some(OPCode::Add) => println!("Add"),
-            Some(OPCode::And) => println!("And"),
-            Some(OPCode::Not) => println!("Not"),
-            Some(OPCode::Br) => println!("Br"),
-            Some(OPCode::Jmp) => println!("Jmp"),
-            Some(OPCode::Jsr) => println!("Jsr"),
-            Some(OPCode::Ld) => println!("Ld"),
-            Some(OPCode::Ldi) => println!("Ldi"),
-            Some(OPCode::Ldr) => println!("Ldr"),
-            Some(OPCode::Lea) => println!("Lea"),
-            Some(OPCode::St) => println!("St"),
-            Some(OPCode::Sti) => println!("Sti"),
-            Some(OPCode::Str) => println!("Str"),
-            Some(OPCode::Trap) => println!("Trap"),
-            Some(OPCode::Res | OPCode::Rti) => {
-                panic!("Unsupported instruction")
+    let Some(path) = spec else {
+        println!("{}", USAGE_CMD);
+        process::exit(1);
+    };
+    let spec = fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| {
+            let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+            Spec::parse(&text, dir).map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{} : {}", path, err);
+            process::exit(1);
+        });
+    let mut outcomes = vec![];
+    for case in &spec.cases {
+        let outcome = spec.run(case).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        if outcome.passed() {
+            println!("ok     {}", outcome.name);
+        } else {
+            println!("FAILED {}", outcome.name);
+            for failure in &outcome.failures {
+                println!("       {}", failure);
             }
-            None => println!("Unknown instruction"),
         }
-        break;
+        outcomes.push(outcome);
+    }
+    let failed = outcomes.iter().filter(|o| !o.passed()).count();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if let Some(report) = junit {
+        if let Err(err) = fs::write(&report, grade::junit(path, &outcomes)) {
+            eprintln!("{} : {}", report, err);
+            process::exit(1);
+        }
+    }
+    if failed > 0 {
+        process::exit(1);
     }
+}
 
-    println!("Hello, world!");
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("run") => run(&args[2..]),
+        Some("debug") => debug(&args[2..]),
+        Some("resume") => resume(&args[2..]),
+        Some("test") => test(&args[2..]),
+        Some("asm") => assemble(&args[2..]),
+        Some("disasm") => disassemble(&args[2..]),
+        _ => println!("{}", USAGE_CMD),
+    }
 }
@@ -0,0 +1,73 @@
+// Operating system support.
+//
+// Traps are either serviced by the host, see `VirtualMachine::trap`, or by
+// the LC-3 operating system image in `os.asm`, which is assembled with our
+// own assembler and loaded at x0000. With the image loaded TRAP jumps through
+// the trap vector table in memory like on the reference simulators.
+use crate::asm;
+use crate::loader::Segment;
+use crate::vm::VirtualMachine;
+
+// Source of the bundled operating system image.
+pub const OS_SOURCE: &str = include_str!("os.asm");
+
+// Who services TRAP instructions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OsMode {
+    // Trap routines are implemented by the host.
+    Native,
+    // Trap routines are LC-3 code found through the trap vector table.
+    Image,
+}
+
+impl VirtualMachine {
+    // Assemble and load the bundled operating system image, traps are then
+    // dispatched through the trap vector table. Returns the memory holding
+    // the image so programs can be kept from overwriting it.
+    pub fn load_os(&mut self) -> Segment {
+        let os = asm::assemble(OS_SOURCE).expect("bundled OS should assemble");
+        let segment = self
+            .load_image("lc3os", &os.to_obj())
+            .expect("bundled OS should fit in memory");
+        self.os = OsMode::Image;
+        segment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::ScriptedConsole;
+    use crate::vm::{Register, RunResult};
+
+    // Echo keys until input runs out.
+    const ECHO: &str = ".ORIG x3000\nLOOP GETC\nOUT\nBRnzp LOOP\n.END";
+
+    // Boot the OS image with an echo program and scripted input.
+    fn boot(input: &[u8]) -> (VirtualMachine, ScriptedConsole) {
+        let console = ScriptedConsole::new(input);
+        let mut vm = VirtualMachine::with_console(Box::new(console.clone()));
+        let os = vm.load_os();
+        let program = asm::assemble(ECHO).unwrap();
+        vm.load_image("echo", &program.to_obj()).unwrap();
+        assert!(program.origin as usize >= os.end());
+        vm.registers[Register::Pc as usize] = program.origin;
+        (vm, console)
+    }
+
+    #[test]
+    fn polling_halts_when_input_runs_out() {
+        let (mut vm, console) = boot(b"hi\n");
+        assert_eq!(vm.run(), RunResult::Halted);
+        assert_eq!(console.output(), b"hi\n");
+    }
+
+    #[cfg(all(target_arch = "x86_64", unix))]
+    #[test]
+    fn polling_halts_when_input_runs_out_jit() {
+        let (mut vm, console) = boot(b"hi\n");
+        let mut jit = crate::jit::Jit::new().unwrap();
+        assert_eq!(jit.run(&mut vm), RunResult::Halted);
+        assert_eq!(console.output(), b"hi\n");
+    }
+}
@@ -0,0 +1,81 @@
+// Tracking of memory pages holding translated code.
+//
+// The translator marks the pages its blocks were translated from, writes to
+// those pages are recorded so stale blocks can be dropped before they run
+// again. Pages are 256 words.
+
+// Number of address bits selecting a word within a page.
+pub const PAGE_BITS: u32 = 8;
+// Number of pages in memory.
+pub const PAGES: usize = 1 << (16 - PAGE_BITS);
+
+// Set of pages, one bit per page.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PageSet([u64; PAGES / 64]);
+
+impl PageSet {
+    fn insert(&mut self, page: usize) {
+        self.0[page / 64] |= 1 << (page % 64);
+    }
+
+    fn contains(&self, page: usize) -> bool {
+        self.0[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|&bits| bits == 0)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CodePages {
+    // Pages blocks were translated from.
+    translated: PageSet,
+    // Translated pages written to since they were last taken.
+    written: PageSet,
+    // Set when `written` isn't empty, checked before every block.
+    dirty: bool,
+}
+
+// Page holding an address.
+pub fn page(address: u16) -> usize {
+    (address >> PAGE_BITS) as usize
+}
+
+impl CodePages {
+    // Record that code from `start` to `end` inclusive was translated.
+    pub fn mark(&mut self, start: u16, end: u16) {
+        for page in page(start)..=page(end) {
+            self.translated.insert(page);
+        }
+    }
+
+    // Record a write, only writes to translated pages are kept.
+    pub fn write(&mut self, address: u16) {
+        let page = page(address);
+        if self.translated.contains(page) {
+            self.written.insert(page);
+            self.dirty = true;
+        }
+    }
+
+    // Whether translated code may have been overwritten.
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // Pages written to since the last call, clearing the record.
+    pub fn take_written(&mut self) -> Vec<usize> {
+        let written = std::mem::take(&mut self.written);
+        self.dirty = false;
+        if written.is_empty() {
+            return vec![];
+        }
+        (0..PAGES).filter(|&page| written.contains(page)).collect()
+    }
+
+    // Forget all translated pages.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
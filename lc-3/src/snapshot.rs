@@ -0,0 +1,291 @@
+// Machine snapshots.
+//
+// A snapshot holds everything needed to resume a machine: the registers, the
+// PSR and saved stack pointers, halt state, OS mode, device registers and all
+// of memory. It is a sequence of big-endian words like object files:
+//
+//     "LC3SNAP" magic and format version
+//     R0-R7, PC, Cond, PSR, saved USP, saved SSP
+//     flags, exception vector or xFFFF, KBDR, MCR
+//     65536 words of memory
+//
+// The console, code pages, decoded instructions and history aren't saved,
+// restoring a snapshot drops translated and decoded code and recorded steps.
+use std::error::Error;
+use std::fmt;
+
+use crate::devices::DeviceState;
+use crate::interrupts::Exception;
+use crate::os::OsMode;
+use crate::pages::{PAGES, PAGE_BITS};
+use crate::vm::{Register, VirtualMachine, MEMORY_MAX};
+
+const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+// Format version, bumped whenever the layout changes.
+pub const VERSION: u16 = 1;
+
+// Bits of the flags word.
+const HALTED: u16 = 1 << 0;
+const OS_IMAGE: u16 = 1 << 1;
+const KEY_READY: u16 = 1 << 2;
+const KEY_INTERRUPTS: u16 = 1 << 3;
+// Exception word of a machine without an exception.
+const NO_EXCEPTION: u16 = 0xffff;
+
+// Words following the magic and version.
+const HEADER_WORDS: usize = Register::Count as usize + 7;
+// Size of a snapshot in bytes.
+pub const SNAPSHOT_LEN: usize =
+    MAGIC.len() + 2 + (HEADER_WORDS + MEMORY_MAX) * 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    // The data doesn't start with the snapshot magic or is truncated.
+    Malformed,
+    // The snapshot was written in a format version we can't read.
+    Version(u16),
+    // The snapshot holds a value the machine can't be in.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Malformed => write!(f, "not an LC-3 snapshot"),
+            SnapshotError::Version(version) => write!(
+                f,
+                "snapshot format version {} isn't supported, expected {}",
+                version, VERSION
+            ),
+            SnapshotError::Invalid(what) => {
+                write!(f, "snapshot holds an invalid {}", what)
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl VirtualMachine {
+    // Save the machine state.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let devices = self.devices.state();
+        let mut flags = 0;
+        for (set, bit) in [
+            (self.halted, HALTED),
+            (self.os == OsMode::Image, OS_IMAGE),
+            (devices.key_ready, KEY_READY),
+            (devices.key_interrupts, KEY_INTERRUPTS),
+        ] {
+            if set {
+                flags |= bit;
+            }
+        }
+        let exception = self.exception.map_or(NO_EXCEPTION, |e| e as u16);
+
+        let mut words = self.registers.to_vec();
+        words.extend([self.psr, self.saved_usp, self.saved_ssp]);
+        words.extend([flags, exception, devices.kbdr, devices.mcr]);
+        words.extend_from_slice(&self.memory);
+
+        let mut bytes = Vec::with_capacity(SNAPSHOT_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        for word in words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Replace the machine state with a snapshot, the console is kept. The
+    // machine is left untouched if the snapshot can't be read.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            return Err(SnapshotError::Malformed);
+        };
+        if rest.len() < 2 {
+            return Err(SnapshotError::Malformed);
+        }
+        let version = u16::from_be_bytes([rest[0], rest[1]]);
+        if version != VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        if bytes.len() != SNAPSHOT_LEN {
+            return Err(SnapshotError::Malformed);
+        }
+        let words: Vec<u16> = rest[2..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        let (header, memory) = words.split_at(HEADER_WORDS);
+        let (registers, header) = header.split_at(Register::Count as usize);
+        let &[psr, saved_usp, saved_ssp, flags, exception, kbdr, mcr] = header
+        else {
+            unreachable!("header is {} words", HEADER_WORDS);
+        };
+        let exception = match exception {
+            NO_EXCEPTION => None,
+            e if e == Exception::PrivilegeViolation as u16 => {
+                Some(Exception::PrivilegeViolation)
+            }
+            e if e == Exception::IllegalOpcode as u16 => {
+                Some(Exception::IllegalOpcode)
+            }
+            _ => return Err(SnapshotError::Invalid("exception")),
+        };
+
+        self.registers.copy_from_slice(registers);
+        self.psr = psr;
+        self.saved_usp = saved_usp;
+        self.saved_ssp = saved_ssp;
+        self.halted = flags & HALTED != 0;
+        self.exception = exception;
+        self.os = if flags & OS_IMAGE != 0 {
+            OsMode::Image
+        } else {
+            OsMode::Native
+        };
+        self.devices.set_state(DeviceState {
+            kbdr,
+            key_ready: flags & KEY_READY != 0,
+            key_interrupts: flags & KEY_INTERRUPTS != 0,
+            mcr,
+        });
+        self.memory.copy_from_slice(memory);
+        // All of memory may have changed under translated code.
+        for page in 0..PAGES {
+            self.code_pages.write((page << PAGE_BITS) as u16);
+        }
+        if let Some(decoded) = &mut self.decoded {
+            decoded.clear();
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::ScriptedConsole;
+    use crate::interrupts::PSR_USER;
+
+    fn machine() -> VirtualMachine {
+        VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")))
+    }
+
+    // Machine with every saved field away from its default.
+    fn busy() -> VirtualMachine {
+        let mut vm = machine();
+        for (r, value) in vm.registers.iter_mut().enumerate() {
+            *value = 0x1111 * (r as u16 + 1);
+        }
+        vm.set_psr(0x0402);
+        vm.saved_usp = 0xFDFF;
+        vm.saved_ssp = 0x2FF0;
+        vm.halted = true;
+        vm.exception = Some(Exception::IllegalOpcode);
+        vm.os = OsMode::Image;
+        vm.devices.set_state(DeviceState {
+            kbdr: 'q' as u16,
+            key_ready: true,
+            key_interrupts: true,
+            mcr: 0x7FFF,
+        });
+        for (address, word) in vm.memory.iter_mut().enumerate() {
+            *word = (address as u16).wrapping_mul(31) ^ 0xA5A5;
+        }
+        vm
+    }
+
+    fn assert_same(a: &VirtualMachine, b: &VirtualMachine) {
+        assert_eq!(a.registers, b.registers);
+        assert_eq!(a.psr(), b.psr());
+        assert_eq!(a.saved_usp, b.saved_usp);
+        assert_eq!(a.saved_ssp, b.saved_ssp);
+        assert_eq!(a.halted, b.halted);
+        assert_eq!(a.exception, b.exception);
+        assert_eq!(a.os, b.os);
+        assert_eq!(a.devices.state(), b.devices.state());
+        assert!(a.memory == b.memory);
+    }
+
+    #[test]
+    fn round_trip() {
+        let vm = busy();
+        let bytes = vm.snapshot();
+        assert_eq!(bytes.len(), SNAPSHOT_LEN);
+        let mut restored = machine();
+        restored.enable_history(10);
+        restored.step();
+        restored.restore(&bytes).unwrap();
+        assert_same(&vm, &restored);
+        assert!(restored.history.as_ref().unwrap().is_empty());
+        assert_eq!(restored.snapshot(), bytes);
+    }
+
+    #[test]
+    fn round_trip_fresh_machine() {
+        let vm = machine();
+        let mut restored = busy();
+        restored.restore(&vm.snapshot()).unwrap();
+        assert_same(&vm, &restored);
+        assert_eq!(restored.psr(), PSR_USER);
+    }
+
+    // Restoring `bytes` fails with `expected` and leaves the machine as it
+    // was.
+    fn assert_rejected(bytes: &[u8], expected: SnapshotError) {
+        let mut vm = busy();
+        assert_eq!(vm.restore(bytes), Err(expected));
+        assert_same(&vm, &busy());
+    }
+
+    #[test]
+    fn wrong_magic() {
+        let mut bytes = machine().snapshot();
+        bytes[0] = b'X';
+        assert_rejected(&bytes, SnapshotError::Malformed);
+        assert_rejected(b"", SnapshotError::Malformed);
+        assert_rejected(b"LC3SNA", SnapshotError::Malformed);
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = machine().snapshot();
+        bytes[8..10].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert_rejected(&bytes, SnapshotError::Version(VERSION + 1));
+        assert_eq!(
+            SnapshotError::Version(VERSION + 1).to_string(),
+            format!(
+                "snapshot format version {} isn't supported, expected {}",
+                VERSION + 1,
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = machine().snapshot();
+        for len in [MAGIC.len(), MAGIC.len() + 1, 100, SNAPSHOT_LEN - 1] {
+            assert_rejected(&bytes[..len], SnapshotError::Malformed);
+        }
+        let mut longer = bytes.clone();
+        longer.extend([0, 0]);
+        assert_rejected(&longer, SnapshotError::Malformed);
+    }
+
+    #[test]
+    fn invalid_exception() {
+        let mut bytes = machine().snapshot();
+        // The exception word follows the magic, version, registers, PSR,
+        // saved stack pointers and flags.
+        let offset = MAGIC.len() + 2 + (Register::Count as usize + 4) * 2;
+        assert_eq!(&bytes[offset..offset + 2], [0xFF, 0xFF]);
+        bytes[offset..offset + 2].copy_from_slice(&[0x00, 0x02]);
+        assert_rejected(&bytes, SnapshotError::Invalid("exception"));
+    }
+}
@@ -0,0 +1,163 @@
+// Symbol tables mapping labels to addresses, stored on disk in the same
+// `.sym` format as the reference `lc3as` assembler.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a symbol, returns false if it was already defined.
+    pub fn insert(&mut self, name: &str, address: u16) -> bool {
+        if self.symbols.contains_key(name) {
+            return false;
+        }
+        self.symbols.insert(name.to_string(), address);
+        true
+    }
+
+    // Address of a symbol.
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    // Name of a symbol defined at an address, the first one in alphabetical
+    // order if several labels share the address.
+    pub fn name_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|(_, &a)| a == address)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // Symbols sorted by address.
+    pub fn sorted(&self) -> Vec<(&str, u16)> {
+        let mut symbols: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, &address)| (name.as_str(), address))
+            .collect();
+        symbols.sort_by_key(|&(name, address)| (address, name));
+        symbols
+    }
+
+    // Symbols from the `.sym` files next to object files, files without one
+    // are skipped.
+    pub fn for_objects(files: &[String]) -> Self {
+        let mut symbols = Self::new();
+        for file in files {
+            let path = Path::new(file).with_extension("sym");
+            if let Ok(text) = fs::read_to_string(path) {
+                for (name, address) in Self::parse(&text).sorted() {
+                    symbols.insert(name, address);
+                }
+            }
+        }
+        symbols
+    }
+
+    // Read a table in the `.sym` file format, lines that aren't a symbol
+    // entry such as the header are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let Some(entry) = line.trim().strip_prefix("//") else {
+                continue;
+            };
+            let mut fields = entry.split_whitespace();
+            if let (Some(name), Some(address), None) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if let Ok(address) = u16::from_str_radix(address, 16) {
+                    table.insert(name, address);
+                }
+            }
+        }
+        table
+    }
+
+    // Render the table in the `.sym` file format.
+    pub fn to_sym(&self) -> String {
+        let mut out = String::from(
+            "// Symbol table\n\
+             // Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n",
+        );
+        for (name, address) in self.sorted() {
+            out.push_str(&format!("//\t{:<16}  {:04X}\n", name, address));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn sym_round_trip() {
+        let program = assemble(
+            "START .ORIG x3000\nLOOP BR LOOP\nA .FILL #1\nB .FILL #2\n\
+             A_LONGER_LABEL_NAME .BLKW #1\nEND .FILL #3\n.END",
+        )
+        .unwrap();
+        let sym = program.symbols.to_sym();
+        let parsed = SymbolTable::parse(&sym);
+        assert_eq!(parsed.sorted(), program.symbols.sorted());
+        assert_eq!(
+            parsed.sorted(),
+            [
+                ("LOOP", 0x3000),
+                ("START", 0x3000),
+                ("A", 0x3001),
+                ("B", 0x3002),
+                ("A_LONGER_LABEL_NAME", 0x3003),
+                ("END", 0x3004),
+            ]
+        );
+        assert_eq!(parsed.to_sym(), sym);
+    }
+
+    #[test]
+    fn parse_skips_other_lines() {
+        let table = SymbolTable::parse(
+            "// Symbol table\n// Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n\
+             //\tMAIN              3000\n\
+             //\tBAD               30G0\n\
+             //\tEXTRA             3001 x\n\
+             DATA 3002\n\
+             //\tlower             fe00\n\n",
+        );
+        assert_eq!(table.sorted(), [("MAIN", 0x3000), ("lower", 0xFE00)]);
+    }
+
+    #[test]
+    fn lookups() {
+        let mut table = SymbolTable::new();
+        assert!(table.is_empty());
+        assert!(table.insert("ZED", 0x3000));
+        assert!(table.insert("ALPHA", 0x3000));
+        assert!(!table.insert("ZED", 0x4000));
+        assert_eq!(table.get("ZED"), Some(0x3000));
+        assert_eq!(table.get("zed"), None);
+        assert_eq!(table.name_at(0x3000), Some("ALPHA"));
+        assert_eq!(table.name_at(0x4000), None);
+    }
+}
@@ -0,0 +1,158 @@
+// Console on an interactive terminal.
+//
+// LC-3 games and shells expect keys as soon as they're typed and echo them
+// themselves, so the terminal is switched out of canonical mode and echo is
+// turned off while the console is alive. Ctrl-C still raises SIGINT, the
+// handler restores the terminal before exiting.
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::sync::OnceLock;
+
+use crate::console::Console;
+
+// Terminal settings before switching to raw mode.
+static SAVED: OnceLock<libc::termios> = OnceLock::new();
+
+// Restore the saved terminal settings.
+fn restore() {
+    if let Some(saved) = SAVED.get() {
+        // SAFETY: `saved` is a valid termios read from the same descriptor.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+    }
+}
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    // Both calls are async signal safe.
+    restore();
+    // SAFETY: exiting without running destructors is what we want here.
+    unsafe { libc::_exit(130) };
+}
+
+#[derive(Debug)]
+pub struct TerminalConsole;
+
+impl TerminalConsole {
+    // Switch standard input to raw mode, returns `None` if it isn't a
+    // terminal, for example when input is piped.
+    pub fn new() -> Option<Self> {
+        // SAFETY: `isatty` and `tcgetattr` only inspect the descriptor and
+        // `termios` is plain data that `tcgetattr` fills in.
+        let mut termios = unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut termios = MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return None;
+            }
+            termios.assume_init()
+        };
+        let saved = termios;
+
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        // SAFETY: `termios` is a valid configuration derived from the current
+        // one.
+        let set = unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios)
+        };
+        if set != 0 {
+            return None;
+        }
+        // Only hook Ctrl-C once there are settings to restore.
+        SAVED.get_or_init(|| saved);
+        // SAFETY: the handler only calls async signal safe functions.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as extern "C" fn(libc::c_int)
+                    as libc::sighandler_t,
+            );
+        }
+        Some(Self)
+    }
+}
+
+impl Drop for TerminalConsole {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+impl Console for TerminalConsole {
+    fn read(&mut self) -> Option<u8> {
+        self.flush();
+        let mut key = 0_u8;
+        // SAFETY: reads at most one byte into `key`.
+        let n = unsafe {
+            libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1)
+        };
+        (n == 1).then_some(key)
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.flush();
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: polls a single valid descriptor without waiting.
+        let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+        if ready == 1 && fd.revents & libc::POLLIN != 0 {
+            self.read()
+        } else {
+            None
+        }
+    }
+
+    fn write(&mut self, c: u8) {
+        io::stdout()
+            .write_all(&[c])
+            .expect("Failed to write to stdout");
+    }
+
+    fn flush(&mut self) {
+        io::stdout().flush().expect("Failed to flush stdout");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piped_input_isnt_a_terminal() {
+        // Swap standard input for a pipe, like `lc-3 run < file` or CI.
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for both ends of the pipe and the original
+        // standard input is put back before returning.
+        let console = unsafe {
+            assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+            let stdin = libc::dup(libc::STDIN_FILENO);
+            libc::dup2(fds[0], libc::STDIN_FILENO);
+            let console = TerminalConsole::new();
+            libc::dup2(stdin, libc::STDIN_FILENO);
+            for fd in [stdin, fds[0], fds[1]] {
+                libc::close(fd);
+            }
+            console
+        };
+        assert!(console.is_none());
+        assert!(SAVED.get().is_none());
+        // SAFETY: only reads the current SIGINT disposition.
+        let handler = unsafe {
+            let mut action = MaybeUninit::<libc::sigaction>::zeroed();
+            libc::sigaction(
+                libc::SIGINT,
+                std::ptr::null(),
+                action.as_mut_ptr(),
+            );
+            action.assume_init().sa_sigaction
+        };
+        assert_eq!(handler, libc::SIG_DFL);
+    }
+}
@@ -0,0 +1,216 @@
+// Instruction tracing and coverage.
+//
+// Traced runs go through the interpreter one instruction at a time, logging
+// each instruction with the registers it changed and the condition codes, and
+// counting how often every address was executed. Coverage is reported per
+// source line in the lcov tracefile format using the assembler line tables.
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::disasm;
+use crate::lines::LineTable;
+use crate::symbols::SymbolTable;
+use crate::vm::{CondFlags, Register, RunResult, VirtualMachine, MEMORY_MAX};
+
+// Execution counts per address.
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    hits: Vec<u64>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            hits: vec![0; MEMORY_MAX],
+        }
+    }
+
+    // Count an execution of the instruction at an address.
+    pub fn record(&mut self, address: u16) {
+        self.hits[address as usize] += 1;
+    }
+
+    // Number of times the instruction at an address was executed.
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    // Render in the lcov tracefile format, one record per source file.
+    pub fn to_lcov(&self, tables: &[LineTable]) -> String {
+        let mut out = String::new();
+        for table in tables {
+            let mut lines = BTreeMap::new();
+            for (address, line) in table.iter() {
+                *lines.entry(line).or_insert(0) += self.hits(address);
+            }
+            out.push_str(&format!("TN:\nSF:{}\n", table.source));
+            for (line, hits) in &lines {
+                out.push_str(&format!("DA:{},{}\n", line, hits));
+            }
+            let hit = lines.values().filter(|&&hits| hits > 0).count();
+            out.push_str(&format!(
+                "LF:{}\nLH:{}\nend_of_record\n",
+                lines.len(),
+                hit
+            ));
+        }
+        out
+    }
+}
+
+// Condition codes as letters.
+pub fn condition_codes(cond: u16) -> String {
+    [
+        (CondFlags::Neg, 'N'),
+        (CondFlags::Zero, 'Z'),
+        (CondFlags::Pos, 'P'),
+    ]
+    .iter()
+    .filter(|(flag, _)| cond & *flag as u16 != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+// Trace line for the instruction `inst` executed at `address`, `before`
+// holds the registers from before it ran.
+pub fn line(
+    address: u16,
+    inst: u16,
+    before: &[u16],
+    vm: &VirtualMachine,
+    symbols: Option<&SymbolTable>,
+) -> String {
+    let mut line = format!("{:<52}", disasm::line(address, inst, symbols));
+    let after = &vm.registers[..8];
+    for (r, (&old, &new)) in before.iter().zip(after).enumerate() {
+        if old != new {
+            line.push_str(&format!("R{}=x{:04X}  ", r, new));
+        }
+    }
+    let cond = vm.registers[Register::Cond as usize];
+    line.push_str(&format!("CC={}", condition_codes(cond)));
+    line
+}
+
+// Run until the machine halts, writing a trace line per instruction to
+// `trace` and counting executed addresses in `coverage`.
+pub fn run(
+    vm: &mut VirtualMachine,
+    symbols: Option<&SymbolTable>,
+    mut trace: Option<&mut dyn Write>,
+    mut coverage: Option<&mut Coverage>,
+) -> io::Result<RunResult> {
+    while !vm.halted {
+        vm.check_interrupts();
+        let address = vm.registers[Register::Pc as usize];
+        let inst = vm.read(address as usize);
+        let before = vm.registers;
+        vm.execute();
+        if let Some(coverage) = coverage.as_deref_mut() {
+            coverage.record(address);
+        }
+        if let Some(trace) = trace.as_deref_mut() {
+            writeln!(trace, "{}", line(address, inst, &before, vm, symbols))?;
+        }
+    }
+    Ok(vm.result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::console::ScriptedConsole;
+
+    const SOURCE: &str = "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #3
+LOOP    ADD R0, R0, #2
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+DATA    .FILL x1234
+        .END";
+
+    // Assemble and run the program, returns its line table after a trip
+    // through the `.dbg` format, the trace and the coverage.
+    fn traced() -> (LineTable, String, Coverage) {
+        let mut program = assemble(SOURCE).unwrap();
+        program.lines.source = "loop.asm".to_string();
+        let lines = LineTable::parse(&program.lines.to_dbg());
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")));
+        vm.load_image("loop", &program.to_obj()).unwrap();
+        vm.registers[Register::Pc as usize] = program.origin;
+        let mut trace = vec![];
+        let mut coverage = Coverage::new();
+        let result = run(
+            &mut vm,
+            Some(&program.symbols),
+            Some(&mut trace),
+            Some(&mut coverage),
+        )
+        .unwrap();
+        assert_eq!(result, RunResult::Halted);
+        (lines, String::from_utf8(trace).unwrap(), coverage)
+    }
+
+    #[test]
+    fn dbg_round_trip() {
+        let program = assemble(SOURCE).unwrap();
+        let (lines, _, _) = traced();
+        assert_eq!(lines.source, "loop.asm");
+        assert_eq!(
+            lines.iter().collect::<Vec<_>>(),
+            program.lines.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(lines.line(0x3000), Some(2));
+        assert_eq!(lines.line(0x3005), Some(7));
+        // Malformed lines are skipped.
+        let table = LineTable::parse("source a.asm\nx3000 4\n3001 5\nx3002\n");
+        assert_eq!(table.iter().collect::<Vec<_>>(), [(0x3000, 4)]);
+    }
+
+    #[test]
+    fn lcov() {
+        let (lines, _, coverage) = traced();
+        assert_eq!(coverage.hits(0x3002), 3);
+        assert_eq!(
+            coverage.to_lcov(&[lines]),
+            "TN:\nSF:loop.asm\n\
+             DA:2,1\nDA:3,1\nDA:4,3\nDA:5,3\nDA:6,3\nDA:7,1\n\
+             LF:6\nLH:6\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn unexecuted_lines() {
+        let mut lines = LineTable::new();
+        lines.source = "a.asm".to_string();
+        lines.insert(0x3000, 1);
+        lines.insert(0x3001, 2);
+        let mut coverage = Coverage::new();
+        coverage.record(0x3000);
+        assert_eq!(
+            coverage.to_lcov(&[lines]),
+            "TN:\nSF:a.asm\nDA:1,1\nDA:2,0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn trace_lines() {
+        let (_, trace, _) = traced();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert!(lines[1].starts_with("x3001"), "{}", lines[1]);
+        assert!(lines[1].contains("R1=x0003"), "{}", lines[1]);
+        assert!(lines[1].ends_with("CC=P"), "{}", lines[1]);
+        assert!(lines[10].ends_with("CC=Z"), "{}", lines[10]);
+    }
+}
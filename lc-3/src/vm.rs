@@ -0,0 +1,934 @@
+use crate::console::{Console, StdConsole};
+use crate::decode::{decode, DecodeCache, Instruction};
+use crate::devices::Devices;
+use crate::history::History;
+use crate::interrupts::{Exception, INITIAL_SSP, PSR_USER};
+use crate::os::OsMode;
+use crate::pages::CodePages;
+
+// Memory for LC-3 VM, has max size 65536 cells.
+pub const MEMORY_MAX: usize = 1 << 16;
+
+// Register definitions.
+#[derive(Copy, Clone, Debug)]
+pub enum Register {
+    R0,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    Pc,
+    Cond,
+    Count,
+}
+
+// Opcodes
+#[derive(Copy, Clone, Debug)]
+pub enum OPCode {
+    Br,
+    Add,
+    Ld,
+    St,
+    Jsr,
+    And,
+    Ldr,
+    Str,
+    Rti,
+    Not,
+    Ldi,
+    Sti,
+    Jmp,
+    Res,
+    Lea,
+    Trap,
+}
+
+impl OPCode {
+    // We can't cast enums to integers (for good reason) this function
+    // allows us to "cast" u16 values in memory as opcodes if they're
+    // a match.
+    pub fn get(op: u16) -> Option<OPCode> {
+        match op {
+            0 => Some(OPCode::Br),
+            1 => Some(OPCode::Add),
+            2 => Some(OPCode::Ld),
+            3 => Some(OPCode::St),
+            4 => Some(OPCode::Jsr),
+            5 => Some(OPCode::And),
+            6 => Some(OPCode::Ldr),
+            7 => Some(OPCode::Str),
+            8 => Some(OPCode::Rti),
+            9 => Some(OPCode::Not),
+            10 => Some(OPCode::Ldi),
+            11 => Some(OPCode::Sti),
+            12 => Some(OPCode::Jmp),
+            13 => Some(OPCode::Res),
+            14 => Some(OPCode::Lea),
+            15 => Some(OPCode::Trap),
+            _ => None,
+        }
+    }
+}
+
+// Condition flags.
+#[derive(Copy, Clone, Debug)]
+pub enum CondFlags {
+    Pos = 1 << 0,
+    Zero = 1 << 1,
+    Neg = 1 << 2,
+}
+
+// Outcome of running the VM until it stops.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunResult {
+    // The program executed a HALT.
+    Halted,
+    // An exception was raised without a handler in the vector table.
+    Unhandled(Exception),
+}
+
+// Virtual machine
+#[derive(Debug)]
+pub struct VirtualMachine {
+    pub registers: [u16; Register::Count as usize],
+    // Written through `mem_write` or `poke` from outside the crate so
+    // translated and decoded code is dropped with the old words.
+    pub(crate) memory: [u16; MEMORY_MAX],
+    // Privilege and priority bits of the processor status register, the
+    // condition codes are kept in the `Cond` register.
+    pub psr: u16,
+    // Stack pointer of the mode that isn't running, R6 holds the other one.
+    pub saved_usp: u16,
+    pub saved_ssp: u16,
+    // Set once the machine halts, stepping a halted machine does nothing.
+    pub halted: bool,
+    // Exception that halted the machine, if any.
+    pub exception: Option<Exception>,
+    // Whether traps are serviced by the host or an OS image in memory.
+    pub os: OsMode,
+    // Memory mapped device registers, including the console used by the
+    // trap routines.
+    pub devices: Devices,
+    // Pages holding translated code and writes to them.
+    pub code_pages: CodePages,
+    // Undo log of recent steps, when reverse execution is enabled.
+    pub history: Option<History>,
+    // Instructions decoded from memory by address, `None` decodes every
+    // instruction as it executes.
+    pub decoded: Option<DecodeCache>,
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualMachine {
+    // Create a new VM instance.
+    pub fn new() -> Self {
+        Self::with_console(Box::<StdConsole>::default())
+    }
+
+    // Create a new VM instance doing I/O through the given console.
+    pub fn with_console(console: Box<dyn Console>) -> Self {
+        Self {
+            registers: [0_u16; Register::Count as usize],
+            memory: [0_u16; MEMORY_MAX],
+            psr: PSR_USER,
+            saved_usp: 0,
+            saved_ssp: INITIAL_SSP,
+            halted: false,
+            exception: None,
+            os: OsMode::Native,
+            devices: Devices::new(console),
+            code_pages: CodePages::default(),
+            history: None,
+            decoded: Some(DecodeCache::new()),
+        }
+    }
+
+    // Take a pending interrupt, then fetch, decode and execute a single
+    // instruction.
+    pub fn step(&mut self) {
+        self.step_with(|_, _| ());
+    }
+
+    // Step like `step`, calling `before` once a pending interrupt is taken
+    // with the machine about to execute the instruction at PC and whether
+    // an interrupt was taken.
+    pub fn step_with(&mut self, before: impl FnOnce(&VirtualMachine, bool)) {
+        if self.halted {
+            return;
+        }
+        if self.history.is_some() {
+            self.record_step();
+        }
+        let interrupted = self.check_interrupts();
+        before(self, interrupted);
+        self.execute();
+    }
+
+    // Fetch, decode and execute the instruction at PC without checking for
+    // interrupts.
+    pub fn execute(&mut self) {
+        // Offset of the next instruction.
+        let offset = self.registers[Register::Pc as usize];
+        // Increment the program counter.
+        self.registers[Register::Pc as usize] =
+            self.registers[Register::Pc as usize].wrapping_add(1);
+        // Fetch the next instruction, decoding it unless it was already.
+        let instruction = match &mut self.decoded {
+            Some(decoded) => decoded.get(offset, &self.memory),
+            None => decode(offset, self.read(offset as usize)),
+        };
+
+        match instruction {
+            Instruction::Add { dr, sr1, sr2 } => {
+                self.add(dr, sr1, self.registers[sr2 as usize])
+            }
+            Instruction::AddImm { dr, sr1, imm } => self.add(dr, sr1, imm),
+            Instruction::And { dr, sr1, sr2 } => {
+                self.and(dr, sr1, self.registers[sr2 as usize])
+            }
+            Instruction::AndImm { dr, sr1, imm } => self.and(dr, sr1, imm),
+            Instruction::Not { dr, sr } => self.not(dr, sr),
+            Instruction::Br { nzp, target } => self.br(nzp, target),
+            Instruction::Jmp { base } => self.jmp(base),
+            Instruction::Jsr { target } => self.jsr(target),
+            Instruction::Jsrr { base } => self.jsrr(base),
+            Instruction::Ld { dr, address } => self.ld(dr, address),
+            Instruction::Ldi { dr, pointer } => self.ldi(dr, pointer),
+            Instruction::Ldr { dr, base, offset } => self.ldr(dr, base, offset),
+            Instruction::Lea { dr, address } => self.lea(dr, address),
+            Instruction::St { sr, address } => self.st(sr, address),
+            Instruction::Sti { sr, pointer } => self.sti(sr, pointer),
+            Instruction::Str { sr, base, offset } => self.str(sr, base, offset),
+            Instruction::Trap { vector } => self.trap(vector),
+            Instruction::Rti => self.rti(),
+            Instruction::Reserved => self.raise(Exception::IllegalOpcode),
+        }
+    }
+
+    // Run until the machine halts.
+    pub fn run(&mut self) -> RunResult {
+        while !self.halted {
+            self.step();
+        }
+        self.result()
+    }
+
+    // Why the machine halted.
+    pub fn result(&self) -> RunResult {
+        match self.exception {
+            Some(exception) => RunResult::Unhandled(exception),
+            None => RunResult::Halted,
+        }
+    }
+
+    // Read from memory at given index.
+    pub fn read(&self, pos: usize) -> u16 {
+        self.memory[pos]
+    }
+
+    // Read from memory at address, device registers are routed to the
+    // device bus.
+    pub fn mem_read(&mut self, address: usize) -> u16 {
+        if Devices::maps(address as u16) {
+            let value = self.devices.read(address as u16);
+            if !self.devices.running() {
+                self.halted = true;
+            }
+            value
+        } else {
+            self.memory[address]
+        }
+    }
+
+    // Write at memory address, device registers are routed to the device bus.
+    pub fn mem_write(&mut self, address: usize, value: u16) {
+        if Devices::maps(address as u16) {
+            self.devices.write(address as u16, value);
+            if !self.devices.running() {
+                self.halted = true;
+            }
+        } else {
+            if let Some(history) = &mut self.history {
+                history.write(address as u16, self.memory[address]);
+            }
+            self.memory_written(address as u16);
+            self.memory[address] = value
+        }
+    }
+
+    // Write at memory address on behalf of a debugger. Device registers are
+    // written as stored like `read` shows them, without reaching the
+    // devices. The write isn't added to the undo log, stepping back keeps
+    // the new value.
+    pub fn poke(&mut self, address: usize, value: u16) {
+        self.memory_written(address as u16);
+        self.memory[address] = value;
+    }
+
+    // Drop translated and decoded code read from an address before it is
+    // written, writes bypassing `mem_write` must call it too.
+    pub fn memory_written(&mut self, address: u16) {
+        self.code_pages.write(address);
+        if let Some(decoded) = &mut self.decoded {
+            decoded.invalidate(address);
+        }
+    }
+
+    // Update condition flags on each register write.
+    pub fn update_flags(&mut self, r: u8) {
+        if self.registers[r as usize] == 0 {
+            self.registers[Register::Cond as usize] = CondFlags::Zero as u16;
+        } else if (self.registers[r as usize] >> 15) != 0 {
+            self.registers[Register::Cond as usize] = CondFlags::Neg as u16;
+        } else {
+            self.registers[Register::Cond as usize] = CondFlags::Pos as u16;
+        }
+    }
+
+    // Execute add instruction, the second operand is the value of a register
+    // or a five-bit immediate.
+    pub fn add(&mut self, dr: u8, sr1: u8, value: u16) {
+        self.registers[dr as usize] =
+            self.registers[sr1 as usize].wrapping_add(value);
+        self.update_flags(dr)
+    }
+
+    // Execute Ldi instruction, load a value from the address stored at
+    // `pointer` into the destination register.
+    pub fn ldi(&mut self, dr: u8, pointer: u16) {
+        let addr = self.mem_read(pointer as usize);
+        self.registers[dr as usize] = self.mem_read(addr as usize);
+        self.update_flags(dr)
+    }
+
+    // Bitwise AND.
+    pub fn and(&mut self, dr: u8, sr1: u8, value: u16) {
+        self.registers[dr as usize] = self.registers[sr1 as usize] & value;
+        self.update_flags(dr)
+    }
+
+    // Bitwise NOT.
+    pub fn not(&mut self, dr: u8, sr: u8) {
+        self.registers[dr as usize] = !self.registers[sr as usize];
+        self.update_flags(dr)
+    }
+
+    // Branch
+    pub fn br(&mut self, nzp: u16, target: u16) {
+        let cond = self.registers[Register::Cond as usize];
+
+        if (nzp & cond) != 0 {
+            self.registers[Register::Pc as usize] = target;
+        }
+    }
+
+    // Jump
+    pub fn jmp(&mut self, base: u8) {
+        self.registers[Register::Pc as usize] = self.registers[base as usize];
+    }
+
+    // Jump to subroutine.
+    pub fn jsr(&mut self, target: u16) {
+        self.registers[Register::R7 as usize] =
+            self.registers[Register::Pc as usize];
+        self.registers[Register::Pc as usize] = target;
+    }
+
+    // Jump to subroutine at the address in a register.
+    pub fn jsrr(&mut self, base: u8) {
+        // Read the base first, it may be R7.
+        let target = self.registers[base as usize];
+        self.registers[Register::R7 as usize] =
+            self.registers[Register::Pc as usize];
+        self.registers[Register::Pc as usize] = target;
+    }
+
+    // Load
+    pub fn ld(&mut self, dr: u8, address: u16) {
+        self.registers[dr as usize] = self.mem_read(address as usize);
+        self.update_flags(dr)
+    }
+
+    // Load register
+    pub fn ldr(&mut self, dr: u8, base: u8, offset: u16) {
+        self.registers[dr as usize] = self.mem_read(
+            self.registers[base as usize].wrapping_add(offset) as usize,
+        );
+        self.update_flags(dr)
+    }
+
+    // Load effective address
+    pub fn lea(&mut self, dr: u8, address: u16) {
+        self.registers[dr as usize] = address;
+        self.update_flags(dr)
+    }
+
+    // Store.
+    pub fn st(&mut self, sr: u8, address: u16) {
+        self.mem_write(address as usize, self.registers[sr as usize])
+    }
+
+    // Store indirect.
+    pub fn sti(&mut self, sr: u8, pointer: u16) {
+        let addr = self.mem_read(pointer as usize);
+        self.mem_write(addr as usize, self.registers[sr as usize])
+    }
+
+    // Store register.
+    pub fn str(&mut self, sr: u8, base: u8, offset: u16) {
+        self.mem_write(
+            self.registers[base as usize].wrapping_add(offset) as usize,
+            self.registers[sr as usize],
+        )
+    }
+
+    // Trap routines are implemented by passing execution to the host language
+    // runtime, in this case Rust. If for example a trap instruction was for
+    // suspending execution it would suspend it and pass it to a Debugger
+    // runtime function where you can step in or out. Same goes for handling
+    // I/O like writing to a terminal or reading from the keyboard.
+    //
+    // The routines behave like the ones in the reference LC-3 operating
+    // system, down to the prompt and halt messages. Input running dry halts
+    // the machine since no key will ever be typed.
+    //
+    // With an OS image loaded the trap vector table is used instead.
+    pub fn trap(&mut self, vector: u8) {
+        self.registers[Register::R7 as usize] =
+            self.registers[Register::Pc as usize];
+        if self.os == OsMode::Image {
+            self.registers[Register::Pc as usize] =
+                self.mem_read(vector as usize);
+            return;
+        }
+        match vector {
+            // GETC : read a single character, it isn't echoed.
+            0x20 => self.getc(),
+            // OUT : write the character in R0[7:0].
+            0x21 => self
+                .devices
+                .console
+                .write(self.registers[Register::R0 as usize] as u8),
+            // PUTS : write the string starting at R0, one character per word.
+            0x22 => {
+                let mut addr = self.registers[Register::R0 as usize];
+                loop {
+                    let c = self.mem_read(addr as usize);
+                    if c == 0 {
+                        break;
+                    }
+                    self.devices.console.write(c as u8);
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            // IN : prompt for a character and echo it.
+            0x23 => {
+                self.puts_host("\nInput a character>");
+                self.getc();
+                if !self.halted {
+                    self.devices
+                        .console
+                        .write(self.registers[Register::R0 as usize] as u8);
+                    self.devices.console.write(b'\n');
+                }
+            }
+            // PUTSP : write the string starting at R0, two characters per word
+            // with the first one in bits [7:0].
+            0x24 => {
+                let mut addr = self.registers[Register::R0 as usize];
+                loop {
+                    let word = self.mem_read(addr as usize);
+                    if word == 0 {
+                        break;
+                    }
+                    self.devices.console.write(word as u8);
+                    if word >> 8 != 0 {
+                        self.devices.console.write((word >> 8) as u8);
+                    }
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            // HALT
+            0x25 => {
+                self.puts_host("\n----- Halting the processor ----- \n");
+                self.halted = true;
+            }
+            // Unknown vectors halt like they do in the reference OS.
+            _ => {
+                self.puts_host("\n\n--- Undefined trap executed ---\n\n");
+                self.halted = true;
+            }
+        }
+        self.devices.console.flush();
+    }
+
+    // Read a character into R0 for GETC and IN.
+    fn getc(&mut self) {
+        match self.devices.getc() {
+            Some(c) => {
+                self.registers[Register::R0 as usize] = c as u16;
+                self.update_flags(Register::R0 as u8);
+            }
+            None => self.halted = true,
+        }
+    }
+
+    // Write a string from the host to the console.
+    fn puts_host(&mut self, s: &str) {
+        for c in s.bytes() {
+            self.devices.console.write(c);
+        }
+    }
+}
+
+// Instruction fields, shared by the handlers and the disassembler.
+
+// Destination register, or source register of stores, in bits [11:9].
+pub fn dr(inst: u16) -> u16 {
+    (inst >> 9) & 0x7
+}
+
+// First source or base register in bits [8:6].
+pub fn sr1(inst: u16) -> u16 {
+    (inst >> 6) & 0x7
+}
+
+// Second source register of ADD and AND in bits [2:0].
+pub fn sr2(inst: u16) -> u16 {
+    inst & 0x7
+}
+
+// Immediate mode flag of ADD and AND in bit 5.
+pub fn imm_mode(inst: u16) -> bool {
+    (inst >> 5) & 0x1 != 0
+}
+
+// Condition codes tested by BR in bits [11:9].
+pub fn nzp(inst: u16) -> u16 {
+    (inst >> 9) & 0x7
+}
+
+// JSR flag in bit 11, set for a PC relative call and clear for JSRR.
+pub fn jsr_long(inst: u16) -> bool {
+    (inst >> 11) & 0x1 != 0
+}
+
+// Sign extended five-bit immediate.
+pub fn imm5(inst: u16) -> u16 {
+    sign_extend(inst & 0x1f, 5)
+}
+
+// Sign extended six-bit base register offset.
+pub fn offset6(inst: u16) -> u16 {
+    sign_extend(inst & 0x3f, 6)
+}
+
+// Sign extended nine-bit PC offset.
+pub fn pc_offset9(inst: u16) -> u16 {
+    sign_extend(inst & 0x1ff, 9)
+}
+
+// Sign extended eleven-bit PC offset of JSR.
+pub fn pc_offset11(inst: u16) -> u16 {
+    sign_extend(inst & 0x7ff, 11)
+}
+
+// Trap vector in bits [7:0].
+pub fn trapvect8(inst: u16) -> u16 {
+    inst & 0xff
+}
+
+// Sign extension for immediate values, sign extension is used
+// to extend values stored in n-bits to m-bits (m > n) and also
+// preserve their sign.
+fn sign_extend(x: u16, bit_count: usize) -> u16 {
+    // To explain this section an example would be better.
+    // First negative numbers are usually encoded using two complements
+    // The steps for two complements are very simple :
+    // Let's say we want the representation of -1.
+    // 1. Start with 1 in binary 5 bits 0b00001
+    // 2. Flip all the bits : 0b11110
+    // 3. Add 1 : 0b11111
+    // Sign extension works as follow we consider the above example extended
+    // to 16 bits.
+    // 1. Shift the bits of x to the left by their size in bits - 1
+    //    0b11111 >> (5 - 1) => 0b1
+    // 2. Do bitwise AND with 1 => 0b1 AND 1 =>1 != 0
+    // 3. Do bitwise OR with the maximum value of the target in this case
+    //    16 bits <=> 0xFFFF and then right shift by the original bit size.
+    // 4. 0b11111 | 0b1111111111111111 <=> 0b1111111111111111<< 5
+    //    which gives us : 0b1111111100000
+    if ((x >> (bit_count - 1)) & 1) != 0 {
+        x | (0xFFFF << bit_count)
+    } else {
+        x
+    }
+}
+
+// Conformance tests, one instruction at a time against the LC-3 ISA
+// reference. Addresses are chosen so operands wrap around x0000 and xFFFF.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::ScriptedConsole;
+    use crate::interrupts::{IVT_BASE, PSR_USER};
+
+    const N: u16 = CondFlags::Neg as u16;
+    const Z: u16 = CondFlags::Zero as u16;
+    const P: u16 = CondFlags::Pos as u16;
+
+    fn machine() -> VirtualMachine {
+        let mut vm =
+            VirtualMachine::with_console(Box::new(ScriptedConsole::new(b"")));
+        vm.registers[Register::Cond as usize] = Z;
+        vm
+    }
+
+    // Execute the instruction `inst` placed at `pc` on a machine prepared by
+    // `setup`. It runs once with decoded instructions cached and once
+    // decoding as it goes, both must agree.
+    fn step(
+        pc: u16,
+        inst: u16,
+        setup: impl Fn(&mut VirtualMachine),
+    ) -> VirtualMachine {
+        let run = |cached: bool| {
+            let mut vm = machine();
+            if !cached {
+                vm.decoded = None;
+            }
+            vm.memory[pc as usize] = inst;
+            vm.registers[Register::Pc as usize] = pc;
+            setup(&mut vm);
+            vm.step();
+            vm
+        };
+        let (vm, uncached) = (run(true), run(false));
+        assert_eq!(vm.registers, uncached.registers);
+        assert_eq!(vm.memory, uncached.memory);
+        vm
+    }
+
+    fn reg(vm: &VirtualMachine, r: Register) -> u16 {
+        vm.registers[r as usize]
+    }
+
+    fn set(vm: &mut VirtualMachine, r: Register, value: u16) {
+        vm.registers[r as usize] = value;
+    }
+
+    #[test]
+    fn add_register() {
+        // ADD R0, R1, R2
+        let vm = step(0x3000, 0x1042, |vm| {
+            set(vm, Register::R1, 0x7fff);
+            set(vm, Register::R2, 1);
+        });
+        assert_eq!(reg(&vm, Register::R0), 0x8000);
+        assert_eq!(reg(&vm, Register::Cond), N);
+        assert_eq!(reg(&vm, Register::Pc), 0x3001);
+
+        let vm = step(0x3000, 0x1042, |vm| {
+            set(vm, Register::R1, 0xffff);
+            set(vm, Register::R2, 1);
+        });
+        assert_eq!(reg(&vm, Register::R0), 0);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn add_immediate() {
+        // ADD R3, R3, #-1
+        let vm = step(0x3000, 0x16ff, |_| ());
+        assert_eq!(reg(&vm, Register::R3), 0xffff);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        // ADD R3, R3, #15
+        let vm = step(0x3000, 0x16ef, |vm| set(vm, Register::R3, 0xfff1));
+        assert_eq!(reg(&vm, Register::R3), 0);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+
+        let vm = step(0x3000, 0x16ef, |vm| set(vm, Register::R3, 0xfff2));
+        assert_eq!(reg(&vm, Register::R3), 1);
+        assert_eq!(reg(&vm, Register::Cond), P);
+    }
+
+    #[test]
+    fn and() {
+        // AND R0, R1, R2
+        let vm = step(0x3000, 0x5042, |vm| {
+            set(vm, Register::R1, 0xf0f0);
+            set(vm, Register::R2, 0x0ff0);
+        });
+        assert_eq!(reg(&vm, Register::R0), 0x00f0);
+        assert_eq!(reg(&vm, Register::Cond), P);
+
+        // AND R0, R1, #0
+        let vm = step(0x3000, 0x5060, |vm| set(vm, Register::R1, 0xffff));
+        assert_eq!(reg(&vm, Register::R0), 0);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+
+        // AND R0, R1, #-16
+        let vm = step(0x3000, 0x5070, |vm| set(vm, Register::R1, 0x8001));
+        assert_eq!(reg(&vm, Register::R0), 0x8000);
+        assert_eq!(reg(&vm, Register::Cond), N);
+    }
+
+    #[test]
+    fn not() {
+        // NOT R0, R1
+        let vm = step(0x3000, 0x907f, |_| ());
+        assert_eq!(reg(&vm, Register::R0), 0xffff);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        let vm = step(0x3000, 0x907f, |vm| set(vm, Register::R1, 0xffff));
+        assert_eq!(reg(&vm, Register::R0), 0);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+
+        let vm = step(0x3000, 0x907f, |vm| set(vm, Register::R1, 0x8000));
+        assert_eq!(reg(&vm, Register::R0), 0x7fff);
+        assert_eq!(reg(&vm, Register::Cond), P);
+    }
+
+    #[test]
+    fn pc_wraps_after_xffff() {
+        // ADD R0, R0, #0
+        let vm = step(0xffff, 0x1020, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0x0000);
+    }
+
+    #[test]
+    fn br() {
+        // BRnzp #-2 from x0000 wraps to xFFFF.
+        let vm = step(0x0000, 0x0ffe, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0xffff);
+
+        // BRnzp #-1 from xFFFF lands on itself.
+        let vm = step(0xffff, 0x0fff, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0xffff);
+
+        // BRp #255 from xFF00 wraps to x0000.
+        let vm = step(0xff00, 0x02ff, |vm| set(vm, Register::Cond, P));
+        assert_eq!(reg(&vm, Register::Pc), 0x0000);
+
+        // BRn #5 isn't taken with Z set.
+        let vm = step(0x3000, 0x0805, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0x3001);
+
+        // BRn #5 is taken with N set, condition codes are kept.
+        let vm = step(0x3000, 0x0805, |vm| set(vm, Register::Cond, N));
+        assert_eq!(reg(&vm, Register::Pc), 0x3006);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        // BR without condition bits is never taken.
+        let vm = step(0x3000, 0x0005, |vm| set(vm, Register::Cond, P));
+        assert_eq!(reg(&vm, Register::Pc), 0x3001);
+    }
+
+    #[test]
+    fn jmp() {
+        // JMP R2
+        let vm = step(0x3000, 0xc080, |vm| set(vm, Register::R2, 0xbeef));
+        assert_eq!(reg(&vm, Register::Pc), 0xbeef);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+
+        // RET
+        let vm = step(0x3000, 0xc1c0, |vm| set(vm, Register::R7, 0x1234));
+        assert_eq!(reg(&vm, Register::Pc), 0x1234);
+    }
+
+    #[test]
+    fn jsr() {
+        // JSR #-1024 from x0000 wraps to xFC01.
+        let vm = step(0x0000, 0x4c00, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0xfc01);
+        assert_eq!(reg(&vm, Register::R7), 0x0001);
+
+        // JSR #1023 from xFFFF wraps to x03FF.
+        let vm = step(0xffff, 0x4bff, |_| ());
+        assert_eq!(reg(&vm, Register::Pc), 0x03ff);
+        assert_eq!(reg(&vm, Register::R7), 0x0000);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn jsrr() {
+        // JSRR R3
+        let vm = step(0x3000, 0x40c0, |vm| set(vm, Register::R3, 0x4000));
+        assert_eq!(reg(&vm, Register::Pc), 0x4000);
+        assert_eq!(reg(&vm, Register::R7), 0x3001);
+
+        // JSRR R7 jumps to the old value of R7.
+        let vm = step(0x3000, 0x41c0, |vm| set(vm, Register::R7, 0x4000));
+        assert_eq!(reg(&vm, Register::Pc), 0x4000);
+        assert_eq!(reg(&vm, Register::R7), 0x3001);
+    }
+
+    #[test]
+    fn ld() {
+        // LD R0, #20 from xFFF0 wraps to x0005.
+        let vm = step(0xfff0, 0x2014, |vm| vm.memory[0x0005] = 0x8000);
+        assert_eq!(reg(&vm, Register::R0), 0x8000);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        // LD R0, #-2 from x0000 wraps to xFFFF.
+        let vm = step(0x0000, 0x21fe, |vm| vm.memory[0xffff] = 7);
+        assert_eq!(reg(&vm, Register::R0), 7);
+        assert_eq!(reg(&vm, Register::Cond), P);
+    }
+
+    #[test]
+    fn ldi() {
+        // LDI R1, #1 reads through the pointer at x3002.
+        let vm = step(0x3000, 0xa201, |vm| {
+            vm.memory[0x3002] = 0x4000;
+            vm.memory[0x4000] = 0x8001;
+        });
+        assert_eq!(reg(&vm, Register::R1), 0x8001);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        // LDI R1, #-2 from x0000 reads the pointer at xFFFF.
+        let vm = step(0x0000, 0xa3fe, |vm| {
+            set(vm, Register::R1, 5);
+            vm.memory[0xffff] = 0x0010;
+        });
+        assert_eq!(reg(&vm, Register::R1), 0);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn ldr() {
+        // LDR R0, R1, #-1 with R1 = x0000 reads xFFFF.
+        let vm = step(0x3000, 0x607f, |vm| vm.memory[0xffff] = 0x1234);
+        assert_eq!(reg(&vm, Register::R0), 0x1234);
+        assert_eq!(reg(&vm, Register::Cond), P);
+
+        // LDR R0, R1, #1 with R1 = xFFFF reads x0000.
+        let vm = step(0x3000, 0x6041, |vm| {
+            set(vm, Register::R1, 0xffff);
+            vm.memory[0x0000] = 0xfedc;
+        });
+        assert_eq!(reg(&vm, Register::R0), 0xfedc);
+        assert_eq!(reg(&vm, Register::Cond), N);
+    }
+
+    #[test]
+    fn lea() {
+        // LEA R0, #-256 from x0000 wraps to xFF01.
+        let vm = step(0x0000, 0xe100, |_| ());
+        assert_eq!(reg(&vm, Register::R0), 0xff01);
+        assert_eq!(reg(&vm, Register::Cond), N);
+
+        // LEA R0, #0 from xFFFF is x0000, memory isn't read.
+        let vm = step(0xffff, 0xe000, |vm| vm.memory[0] = 0x8000);
+        assert_eq!(reg(&vm, Register::R0), 0x0000);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn st() {
+        // ST R0, #-2 from x0000 wraps to xFFFF, flags are kept.
+        let vm = step(0x0000, 0x31fe, |vm| {
+            set(vm, Register::R0, 0xabcd);
+            set(vm, Register::Cond, P);
+        });
+        assert_eq!(vm.memory[0xffff], 0xabcd);
+        assert_eq!(reg(&vm, Register::Cond), P);
+        assert_eq!(reg(&vm, Register::Pc), 0x0001);
+    }
+
+    #[test]
+    fn sti() {
+        // STI R0, #1 writes through the pointer at x3002.
+        let vm = step(0x3000, 0xb001, |vm| {
+            set(vm, Register::R0, 0x8000);
+            vm.memory[0x3002] = 0x4000;
+        });
+        assert_eq!(vm.memory[0x4000], 0x8000);
+        assert_eq!(vm.memory[0x3002], 0x4000);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn str() {
+        // STR R0, R1, #31 with R1 = xFFF0 writes x000F.
+        let vm = step(0x3000, 0x705f, |vm| {
+            set(vm, Register::R0, 42);
+            set(vm, Register::R1, 0xfff0);
+        });
+        assert_eq!(vm.memory[0x000f], 42);
+
+        // STR R0, R1, #-32 with R1 = x0000 writes xFFE0.
+        let vm = step(0x3000, 0x7060, |vm| set(vm, Register::R0, 43));
+        assert_eq!(vm.memory[0xffe0], 43);
+        assert_eq!(reg(&vm, Register::Cond), Z);
+    }
+
+    #[test]
+    fn trap() {
+        // TRAP x25 halts natively and saves the return address.
+        let vm = step(0x3000, 0xf025, |_| ());
+        assert!(vm.halted);
+        assert_eq!(reg(&vm, Register::R7), 0x3001);
+
+        // With an OS image it jumps through the trap vector table.
+        let vm = step(0x3000, 0xf025, |vm| {
+            vm.os = OsMode::Image;
+            vm.memory[0x25] = 0x0500;
+        });
+        assert!(!vm.halted);
+        assert_eq!(reg(&vm, Register::Pc), 0x0500);
+        assert_eq!(reg(&vm, Register::R7), 0x3001);
+    }
+
+    #[test]
+    fn rti() {
+        // RTI in supervisor mode pops PC and PSR and switches stacks.
+        let vm = step(0x0600, 0x8000, |vm| {
+            vm.psr = 0;
+            set(vm, Register::R6, 0x2ffe);
+            vm.saved_usp = 0xfe00 - 1;
+            vm.memory[0x2ffe] = 0x3005;
+            vm.memory[0x2fff] = PSR_USER | N;
+        });
+        assert_eq!(reg(&vm, Register::Pc), 0x3005);
+        assert_eq!(reg(&vm, Register::Cond), N);
+        assert_eq!(reg(&vm, Register::R6), 0xfdff);
+        assert_eq!(vm.saved_ssp, 0x3000);
+        assert!(vm.user_mode());
+
+        // RTI in user mode is a privilege violation.
+        let vm = step(0x3000, 0x8000, |_| ());
+        assert_eq!(
+            vm.result(),
+            RunResult::Unhandled(Exception::PrivilegeViolation)
+        );
+
+        // With a handler, the exception enters it on the supervisor stack.
+        let vm = step(0x3000, 0x8000, |vm| {
+            vm.memory[IVT_BASE as usize] = 0x1000;
+            set(vm, Register::R6, 0xabcd);
+        });
+        assert_eq!(reg(&vm, Register::Pc), 0x1000);
+        assert_eq!(reg(&vm, Register::R6), 0x2ffe);
+        assert_eq!(vm.saved_usp, 0xabcd);
+        assert_eq!(vm.memory[0x2ffe], 0x3001);
+        assert_eq!(vm.memory[0x2fff], PSR_USER | Z);
+    }
+
+    #[test]
+    fn reserved() {
+        // The reserved opcode raises an illegal opcode exception.
+        let vm = step(0x3000, 0xd000, |_| ());
+        assert!(vm.halted);
+        assert_eq!(vm.result(), RunResult::Unhandled(Exception::IllegalOpcode));
+    }
+}